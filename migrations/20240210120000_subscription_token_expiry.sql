@@ -0,0 +1,3 @@
+ALTER TABLE subscription_tokens
+    ADD created_at timestamptz NOT NULL DEFAULT now(),
+    ADD expires_at timestamptz NOT NULL DEFAULT now() + interval '1 day';
@@ -0,0 +1,6 @@
+-- Tokens are only ever stored as SHA-256 digests from now on. Existing rows
+-- are rehashed in place so that confirmation links already sent keep working.
+UPDATE subscription_tokens
+SET subscription_token = encode(sha256(convert_to(subscription_token, 'UTF8')), 'hex');
+
+ALTER TABLE subscription_tokens RENAME COLUMN subscription_token TO subscription_token_hash;
@@ -0,0 +1,20 @@
+-- What each subscriber agreed to and when, to answer data access requests
+CREATE TABLE consent_events (
+    id uuid NOT NULL PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+
+-- Successful deliveries, the queue only keeps those still to be attempted
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    delivered_at timestamptz NOT NULL,
+
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
+
+CREATE INDEX issue_deliveries_subscriber_email_idx ON issue_deliveries (subscriber_email);
@@ -0,0 +1,22 @@
+-- Tokens go away with their subscriber
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT fk_subscriber_id,
+    ADD CONSTRAINT fk_subscriber_id FOREIGN KEY (subscriber_id)
+        REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- Erased subscribers, as keyed hashes of their email address: imports can
+-- tell they must not be added back without keeping the address around
+CREATE TABLE suppressed_emails (
+    email_hash TEXT NOT NULL PRIMARY KEY,
+    suppressed_at timestamptz NOT NULL
+);
+
+-- Sensitive operations performed by operators. `user_id` is NULL when the
+-- operation was run from the command line.
+CREATE TABLE audit_events (
+    id uuid NOT NULL PRIMARY KEY,
+    user_id uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    subject_id uuid,
+    occurred_at timestamptz NOT NULL
+);
@@ -0,0 +1,43 @@
+-- Mailing lists a subscriber can join, e.g. product updates or the engineering blog
+CREATE TABLE lists (
+    list_id uuid NOT NULL PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- Each list is confirmed, and can be left, separately. The subscription
+-- status still applies on top: an unconfirmed or erased subscriber gets no
+-- issue whatever their memberships.
+CREATE TABLE list_memberships (
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+
+    PRIMARY KEY(list_id, subscriber_id)
+);
+
+CREATE INDEX list_memberships_subscriber_id_idx ON list_memberships (subscriber_id);
+
+-- The lists an issue was published to
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+
+    PRIMARY KEY(newsletter_issue_id, list_id)
+);
+
+-- Everything so far was the one newsletter
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter', now());
+
+INSERT INTO list_memberships (list_id, subscriber_id, status, created_at)
+SELECT lists.list_id, subscriptions.id, subscriptions.status, subscriptions.subscribed_at
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter' AND subscriptions.status <> 'erased';
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issues.newsletter_issue_id, lists.list_id
+FROM newsletter_issues, lists
+WHERE lists.slug = 'newsletter';
@@ -0,0 +1,7 @@
+-- How often a subscriber receives issues, and until when they asked for a break
+ALTER TABLE subscriptions
+    ADD COLUMN delivery_frequency TEXT NOT NULL DEFAULT 'immediate',
+    ADD COLUMN paused_until timestamptz;
+
+-- Set on tokens confirming a change of address: the address to switch to
+ALTER TABLE subscription_tokens ADD COLUMN new_email TEXT;
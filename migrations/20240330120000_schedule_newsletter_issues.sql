@@ -0,0 +1,8 @@
+-- Scheduled issues are stored right away but only published, and their
+-- deliveries queued, once `scheduled_at` is reached
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+CREATE INDEX newsletter_issues_scheduled_at_idx
+ON newsletter_issues (scheduled_at)
+WHERE published_at IS NULL;
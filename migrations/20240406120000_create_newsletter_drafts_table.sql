@@ -0,0 +1,11 @@
+-- Issues being written, until they are published through the newsletter form
+CREATE TABLE newsletter_drafts (
+    draft_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+
+    PRIMARY KEY(draft_id)
+);
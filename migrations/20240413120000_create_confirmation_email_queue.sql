@@ -0,0 +1,7 @@
+-- Confirmation emails of imported subscribers, sent by the background worker
+-- rather than while the import request is open
+CREATE TABLE confirmation_email_queue (
+    subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now()
+);
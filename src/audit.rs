@@ -0,0 +1,37 @@
+use chrono::Utc;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Operations that are recorded in `audit_events` when they are performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditAction {
+    SubscriberDeleted,
+    SubscriberAnonymized,
+}
+
+/// Records that `action` was performed on `subject_id` by the admin `user_id`,
+/// or from the command line when there is no user.
+#[tracing::instrument(name = "Recording an audit event", skip(executor))]
+pub async fn record_audit_event(
+    executor: impl PgExecutor<'_>,
+    user_id: Option<Uuid>,
+    action: AuditAction,
+    subject_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (id, user_id, action, subject_id, occurred_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        action.to_string(),
+        subject_id,
+        Utc::now()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use std::io::Write;
+
+use crate::{domain::Email, email_client::EmailClient};
+
+pub async fn send_test_email(
+    email_client: &EmailClient,
+    recipient: &Email,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    email_client
+        .send_email(
+            recipient,
+            "zero2prod test email",
+            "<p>Emails sent by zero2prod reach this address.</p>",
+            "Emails sent by zero2prod reach this address.",
+        )
+        .await?;
+    writeln!(out, "Sent a test email to {}", recipient.as_ref())?;
+
+    Ok(())
+}
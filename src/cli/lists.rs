@@ -0,0 +1,32 @@
+use std::io::Write;
+
+use sqlx::PgPool;
+
+use crate::{
+    domain::ListSlug,
+    lists::{self, all_lists},
+};
+
+pub async fn create_list(
+    pool: &PgPool,
+    slug: &ListSlug,
+    name: &str,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let list_id = lists::create_list(pool, slug, name).await?;
+    writeln!(out, "Created {slug} ({list_id})")?;
+
+    Ok(())
+}
+
+pub async fn print_lists(pool: &PgPool, out: &mut impl Write) -> Result<(), anyhow::Error> {
+    for list in all_lists(pool).await? {
+        writeln!(
+            out,
+            "{}\t{}\t{} subscriber(s)",
+            list.slug, list.name, list.n_subscribers
+        )?;
+    }
+
+    Ok(())
+}
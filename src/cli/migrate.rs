@@ -0,0 +1,64 @@
+use std::io::Write;
+
+use clap::Subcommand;
+use sqlx::PgPool;
+
+use crate::migrations::{migration_status, run_migrations, MigrationState};
+
+#[derive(Subcommand)]
+pub enum MigrateAction {
+    /// Apply the pending migrations
+    Up,
+    /// List the migrations and whether they are applied
+    Status,
+    /// List the migrations `up` would apply, without applying them
+    DryRun,
+}
+
+pub async fn migrate(
+    pool: &PgPool,
+    action: MigrateAction,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let status = migration_status(pool).await?;
+    let pending: Vec<_> = status
+        .iter()
+        .filter(|migration| migration.state == MigrationState::Pending)
+        .collect();
+
+    match action {
+        MigrateAction::Up => {
+            run_migrations(pool).await?;
+            for migration in &pending {
+                writeln!(
+                    out,
+                    "Applied {} {}",
+                    migration.version, migration.description
+                )?;
+            }
+        }
+        MigrateAction::Status => {
+            for migration in &status {
+                writeln!(
+                    out,
+                    "{:<8} {} {}",
+                    migration.state, migration.version, migration.description
+                )?;
+            }
+        }
+        MigrateAction::DryRun => {
+            for migration in &pending {
+                writeln!(
+                    out,
+                    "Would apply {} {}",
+                    migration.version, migration.description
+                )?;
+            }
+        }
+    }
+    if pending.is_empty() && !matches!(action, MigrateAction::Status) {
+        writeln!(out, "The database is up to date")?;
+    }
+
+    Ok(())
+}
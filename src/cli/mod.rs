@@ -0,0 +1,194 @@
+//! The `zero2prod` command line: the server itself plus the commands
+//! operators use for day-to-day administration.
+
+use std::{io::Write, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+
+use crate::{
+    clock::Clock,
+    configuration::Settings,
+    confirmation_email_worker::run_confirmation_worker_until_stopped,
+    domain::{Email, ListSlug, SubscriberStatus},
+    erasure::ErasureMode,
+    issue_delivery_worker::run_worker_until_stopped,
+    lists::DEFAULT_LIST,
+    newsletter_scheduler::run_scheduler_until_stopped,
+    pending_subscriber_cleanup::run_cleanup_until_stopped,
+    startup::{get_connection_pool, Application},
+    subscriber_export::ExportFormat,
+    subscriber_import::ImportMode,
+};
+
+mod email;
+mod lists;
+mod migrate;
+mod subscribers;
+mod users;
+
+pub use email::send_test_email;
+pub use lists::{create_list, print_lists};
+pub use migrate::{migrate, MigrateAction};
+pub use subscribers::{
+    erase, export_subscriber_data, export_subscribers, import_subscribers, list_subscribers,
+    purge_pending,
+};
+pub use users::create_admin;
+
+#[derive(Parser)]
+#[command(version, about = "Newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API and run the background workers (the default)
+    Serve,
+    /// Manage the database schema
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Create a user who can log into the admin dashboard, reading their
+    /// password from the first line of stdin
+    CreateAdmin { username: String },
+    /// List subscribers, most recent first
+    ListSubscribers {
+        /// Only list subscribers with this status (pending_confirmation, ok, unsubscribed, erased)
+        #[arg(long)]
+        status: Option<SubscriberStatus>,
+    },
+    /// Create a mailing list subscribers can join
+    CreateList {
+        /// Short identifier used in forms and in the API, e.g. `product-updates`
+        slug: String,
+        /// Name shown to subscribers
+        name: String,
+    },
+    /// List the mailing lists with their number of confirmed subscribers
+    Lists,
+    /// Import subscribers from a CSV file with `email` and `name` columns
+    Import {
+        path: PathBuf,
+        /// Whether the subscribers are confirmed already or are sent a confirmation email
+        #[arg(long, value_enum, default_value_t)]
+        mode: ImportMode,
+        /// Slug of the list to add them to
+        #[arg(long, default_value = DEFAULT_LIST)]
+        list: String,
+    },
+    /// Export subscribers
+    Export {
+        /// Only export subscribers with this status (pending_confirmation, ok, unsubscribed, erased)
+        #[arg(long)]
+        status: Option<SubscriberStatus>,
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Where to write the export, stdout if omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Print everything held on a subscriber as JSON, to answer a data access request
+    ExportSubscriberData { email: String },
+    /// Delete or anonymize a subscriber at their request, and keep their
+    /// address from being imported again
+    Erase {
+        email: String,
+        #[arg(long, value_enum, default_value_t)]
+        mode: ErasureMode,
+    },
+    /// Send an email to check the email transport configuration
+    SendTestEmail { recipient: String },
+    /// Delete subscribers who never confirmed and whose confirmation link expired
+    PurgePending,
+}
+
+/// Runs `command`, writing what it has to report to `out`.
+pub async fn run(
+    command: Command,
+    config: Settings,
+    out: &mut (impl Write + Send),
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+    let outcome = match command {
+        Command::Serve => serve(config).await,
+        Command::Migrate { action } => migrate(&pool, action, out).await,
+        Command::CreateAdmin { username } => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = Secret::new(password.trim_end_matches(['\r', '\n']).to_string());
+            create_admin(&pool, &username, password, out).await
+        }
+        Command::ListSubscribers { status } => list_subscribers(&pool, status, out).await,
+        Command::CreateList { slug, name } => {
+            let slug = ListSlug::parse(slug).map_err(anyhow::Error::msg)?;
+            create_list(&pool, &slug, &name, out).await
+        }
+        Command::Lists => print_lists(&pool, out).await,
+        Command::Import { path, mode, list } => {
+            let list = ListSlug::parse(list).map_err(anyhow::Error::msg)?;
+            let file = std::fs::File::open(&path)?;
+            let salt = config.application.suppression_salt();
+            import_subscribers(&pool, file, mode, &list, &salt, out).await
+        }
+        Command::Export {
+            status,
+            format,
+            output,
+        } => match output {
+            Some(path) => {
+                let file = std::fs::File::create(path)?;
+                export_subscribers(&pool, status, format, file).await
+            }
+            None => export_subscribers(&pool, status, format, &mut *out).await,
+        },
+        Command::ExportSubscriberData { email } => {
+            let email = Email::parse(email).map_err(anyhow::Error::msg)?;
+            export_subscriber_data(&pool, &email, out).await
+        }
+        Command::Erase { email, mode } => {
+            let email = Email::parse(email).map_err(anyhow::Error::msg)?;
+            let salt = config.application.suppression_salt();
+            erase(&pool, &salt, &email, mode, out).await
+        }
+        Command::SendTestEmail { recipient } => {
+            let recipient = Email::parse(recipient).map_err(anyhow::Error::msg)?;
+            send_test_email(&config.email.client(), &recipient, out).await
+        }
+        Command::PurgePending => purge_pending(&pool, out).await,
+    };
+    pool.close().await;
+
+    outcome
+}
+
+async fn serve(config: Settings) -> Result<(), anyhow::Error> {
+    let clock = Clock::default();
+    let app = Application::build_with_clock(config.clone(), clock.clone()).await?;
+    let worker_config = config.clone();
+    app.spawn_background_task("Background worker", |shutdown| {
+        run_worker_until_stopped(worker_config, shutdown)
+    });
+    let scheduler_config = config.clone();
+    let confirmation_worker_config = config.clone();
+    let confirmation_worker_clock = clock.clone();
+    app.spawn_background_task("Confirmation email worker", |shutdown| {
+        run_confirmation_worker_until_stopped(
+            confirmation_worker_config,
+            confirmation_worker_clock,
+            shutdown,
+        )
+    });
+    app.spawn_background_task("Newsletter scheduler", |shutdown| {
+        run_scheduler_until_stopped(scheduler_config, clock, shutdown)
+    });
+    app.spawn_background_task("Pending subscriber cleanup", |shutdown| {
+        run_cleanup_until_stopped(config, shutdown)
+    });
+
+    app.run_until_stopped().await?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::io::{Read, Write};
+
+use std::pin::pin;
+
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+
+use crate::{
+    clock::Clock,
+    configuration::SuppressionSalt,
+    domain::{Email, ListSlug, SubscriberStatus},
+    erasure::{erase_subscriber, ErasureMode},
+    lists::find_list_id,
+    pending_subscriber_cleanup::purge_expired_pending_subscribers,
+    subscriber_export::{self, subscriber_data, subscribers, ExportFormat},
+    subscriber_import::{self, ImportMode, LineOutcome},
+};
+
+pub async fn list_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriberStatus>,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let mut subscribers = subscribers(pool, status);
+    let mut n_subscribers = 0;
+    while let Some(subscriber) = subscribers
+        .try_next()
+        .await
+        .context("Failed to fetch subscribers")?
+    {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            subscriber.email,
+            subscriber.name,
+            subscriber.status,
+            subscriber.subscribed_at.to_rfc3339()
+        )?;
+        n_subscribers += 1;
+    }
+    writeln!(out, "{n_subscribers} subscriber(s)")?;
+
+    Ok(())
+}
+
+pub async fn export_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriberStatus>,
+    format: ExportFormat,
+    mut out: impl Write,
+) -> Result<(), anyhow::Error> {
+    let mut chunks = pin!(subscriber_export::export_subscribers(
+        pool.clone(),
+        status,
+        format
+    ));
+    while let Some(chunk) = chunks.try_next().await? {
+        out.write_all(&chunk)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Prints everything held on a subscriber as JSON, for a data access request.
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    email: &Email,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let Some(data) = subscriber_data(pool, email).await? else {
+        anyhow::bail!("There is no subscriber with the email address {email}.");
+    };
+    serde_json::to_writer_pretty(&mut *out, &data)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+/// Imports the subscribers listed in a CSV file into `list`, printing the
+/// outcome of every line.
+pub async fn import_subscribers(
+    pool: &PgPool,
+    input: impl Read,
+    mode: ImportMode,
+    list: &ListSlug,
+    salt: &SuppressionSalt,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let list_id = find_list_id(pool, list).await?;
+    // Commands run on the system clock
+    let clock = Clock::default();
+    let report =
+        subscriber_import::import_subscribers(pool, input, mode, list_id, salt, &clock).await?;
+    for line in &report.lines {
+        let outcome = match &line.outcome {
+            LineOutcome::Accepted => "imported".to_string(),
+            LineOutcome::ConfirmationQueued => "imported, confirmation email queued".to_string(),
+            LineOutcome::AlreadySubscribed => "skipped, already subscribed".to_string(),
+            LineOutcome::Rejected(reason) => format!("rejected, {reason}"),
+        };
+        writeln!(out, "Line {}: {} {}", line.line, line.email, outcome)?;
+    }
+    writeln!(
+        out,
+        "Imported {} subscriber(s), skipped {} already subscribed and rejected {}",
+        report.n_accepted(),
+        report.n_already_subscribed(),
+        report.n_rejected()
+    )?;
+
+    Ok(())
+}
+
+pub async fn erase(
+    pool: &PgPool,
+    salt: &SuppressionSalt,
+    email: &Email,
+    mode: ErasureMode,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let erased = erase_subscriber(pool, salt, email, mode, None).await?;
+    if erased.is_empty() {
+        writeln!(
+            out,
+            "There is no subscriber with the email address {email}, it was added to the suppression list"
+        )?;
+    } else {
+        writeln!(out, "Erased {email}")?;
+    }
+
+    Ok(())
+}
+
+pub async fn purge_pending(pool: &PgPool, out: &mut impl Write) -> Result<(), anyhow::Error> {
+    let n_purged = purge_expired_pending_subscribers(pool).await?;
+    writeln!(out, "Purged {n_purged} pending subscriber(s)")?;
+
+    Ok(())
+}
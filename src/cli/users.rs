@@ -0,0 +1,30 @@
+use std::io::Write;
+
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::authentication::{
+    create_user, has_valid_password_length, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH,
+};
+
+pub async fn create_admin(
+    pool: &PgPool,
+    username: &str,
+    password: Secret<String>,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    if username.trim().is_empty() {
+        anyhow::bail!("The username cannot be empty.");
+    }
+    if !has_valid_password_length(&password) {
+        anyhow::bail!(
+            "The password must be between {MIN_PASSWORD_LENGTH} and \
+            {MAX_PASSWORD_LENGTH} characters long."
+        );
+    }
+
+    let user_id = create_user(username, password, pool).await?;
+    writeln!(out, "Created {username} ({user_id})")?;
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Where the application reads the current time from. It follows the system
+/// clock unless frozen, which lets tests pin timestamps and move time forward
+/// to when a scheduled issue is due.
+#[derive(Clone, Default)]
+pub struct Clock {
+    frozen_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        self.frozen_at
+            .read()
+            .expect("The clock lock is poisoned")
+            .unwrap_or_else(Utc::now)
+    }
+
+    /// Stops the clock at `at`, until it is frozen again or advanced.
+    pub fn freeze_at(&self, at: DateTime<Utc>) {
+        *self.frozen_at.write().expect("The clock lock is poisoned") = Some(at);
+    }
+
+    /// Moves the clock forward, leaving it frozen.
+    pub fn advance(&self, by: Duration) {
+        self.freeze_at(self.now() + by);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Clock;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn a_frozen_clock_only_moves_when_advanced() {
+        let clock = Clock::default();
+        let at = Utc.with_ymd_and_hms(2024, 3, 4, 8, 0, 0).unwrap();
+
+        clock.freeze_at(at);
+        assert_eq!(clock.now(), at);
+
+        clock.advance(Duration::hours(1));
+        assert_eq!(clock.now(), at + Duration::hours(1));
+    }
+
+    #[test]
+    fn clones_share_the_same_time() {
+        let clock = Clock::default();
+        let at = Utc.with_ymd_and_hms(2024, 3, 4, 8, 0, 0).unwrap();
+
+        clock.clone().freeze_at(at);
+
+        assert_eq!(clock.now(), at);
+    }
+}
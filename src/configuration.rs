@@ -1,9 +1,16 @@
+use std::net::IpAddr;
+
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::{postgres::PgConnectOptions, ConnectOptions};
 
-use crate::domain::Email;
+use crate::{
+    domain::Email,
+    email_client::{EmailClient, FileTransport, PostmarkTransport, SmtpTransport},
+};
 
 #[derive(strum::Display, Debug)]
 pub enum Environment {
@@ -33,17 +40,149 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email: EmailClientSettings,
+    pub telemetry: TelemetrySettings,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct ApplicationBaseUrl(pub String);
 
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+/// Key of the hashes kept for erased email addresses. Changing it lets every
+/// erased address be imported again.
+#[derive(Clone)]
+pub struct SuppressionSalt(pub Secret<String>);
+
+#[derive(Clone, Debug)]
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+#[derive(Clone, Debug)]
+pub struct ConfirmationResendCooldown(pub chrono::Duration);
+
+/// The only addresses drafts can be sent to as a test.
+#[derive(Clone, Debug)]
+pub struct TestRecipients(pub Vec<String>);
+
+impl TestRecipients {
+    pub fn allows(&self, email: &Email) -> bool {
+        self.0
+            .iter()
+            .any(|recipient| recipient.trim().eq_ignore_ascii_case(email.as_ref()))
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: Secret<String>,
+    pub suppression_salt: Secret<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: i64,
+    /// How long in-flight requests and background tasks get to complete on shutdown.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_grace_period_seconds: u64,
+    pub rate_limit: RateLimitSettings,
+    pub readiness: ReadinessSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    /// The editors' own addresses, for test sends of drafts.
+    #[serde(default, deserialize_with = "deserialize_list")]
+    pub test_recipients: Vec<String>,
+}
+
+impl ApplicationSettings {
+    pub fn suppression_salt(&self) -> SuppressionSalt {
+        SuppressionSalt(self.suppression_salt.clone())
+    }
+
+    pub fn subscription_token_ttl(&self) -> SubscriptionTokenTtl {
+        SubscriptionTokenTtl(chrono::Duration::hours(self.subscription_token_ttl_hours))
+    }
+
+    pub fn shutdown_grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_grace_period_seconds)
+    }
+
+    pub fn test_recipients(&self) -> TestRecipients {
+        TestRecipients(self.test_recipients.clone())
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReadinessSettings {
+    /// How long each readiness check can take before it is considered failed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// Whether `/readyz` also checks that the email transport is reachable.
+    pub check_email_transport: bool,
+}
+
+impl ReadinessSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct MetricsSettings {
+    /// Serves `/metrics` on its own port, e.g. one that is not exposed
+    /// publicly, instead of next to the other routes.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    /// Number of subscription requests a client can send in a row.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscribe_burst: u32,
+    /// Rate at which a client gets its subscription requests back.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscribe_per_minute: u32,
+    /// Reverse proxies whose `X-Forwarded-For` header is trusted to carry the client IP.
+    #[serde(default, deserialize_with = "deserialize_list")]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Minimum delay before a confirmation email is sent again to the same address.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_resend_cooldown_seconds: i64,
+}
+
+/// Accepts either a list or a comma separated string, the only way to pass a
+/// list through an environment variable.
+fn deserialize_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum List<T> {
+        List(Vec<T>),
+        CommaSeparated(String),
+    }
+
+    match List::deserialize(deserializer)? {
+        List::List(items) => Ok(items),
+        List::CommaSeparated(items) => items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse().map_err(serde::de::Error::custom))
+            .collect(),
+    }
+}
+
+impl RateLimitSettings {
+    pub fn confirmation_resend_cooldown(&self) -> ConfirmationResendCooldown {
+        ConfirmationResendCooldown(chrono::Duration::seconds(
+            self.confirmation_resend_cooldown_seconds,
+        ))
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -56,14 +195,89 @@ pub struct DatabaseSettings {
     pub database_name: String,
     #[serde(default)]
     pub require_ssl: bool,
+    /// Applies the pending migrations when the application starts. Off by
+    /// default: `zero2prod migrate up` does it as a separate deployment step.
+    #[serde(default)]
+    pub migrate_on_startup: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TelemetrySettings {
+    pub otlp: OtlpSettings,
+    /// Fraction of the traces started here that are recorded, between 0 and 1.
+    /// Requests carrying a `traceparent` follow the caller's decision instead.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sampling_ratio: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OtlpSettings {
+    /// Spans are only exported when enabled, they are logged either way.
+    pub enabled: bool,
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+    /// The collector address, e.g. `http://localhost:4317` for gRPC or
+    /// `http://localhost:4318` for HTTP (`/v1/traces` is appended).
+    pub endpoint: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+impl OtlpSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpProtocol {
+    #[default]
+    Grpc,
+    Http,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransportKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub token: Secret<String>,
     pub timeout_miliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u16,
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileSinkSettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FileSinkSettings {
+    pub directory: String,
 }
 
 impl EmailClientSettings {
@@ -74,6 +288,36 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_miliseconds)
     }
+
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Bad sender email");
+        let timeout = self.timeout();
+
+        match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(self.base_url, self.token, timeout),
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The SMTP transport requires `email.smtp` settings");
+                let credentials = smtp.username.zip(smtp.password);
+                let transport =
+                    SmtpTransport::new(&smtp.host, smtp.port, credentials, smtp.starttls, timeout)
+                        .expect("Failed to configure the SMTP transport");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::File => {
+                let file = self
+                    .file
+                    .expect("The file transport requires `email.file` settings");
+                let transport = FileTransport::new(file.directory)
+                    .expect("Failed to create the email file sink directory");
+                EmailClient::new(sender_email, transport)
+            }
+        }
+    }
 }
 
 impl Settings {
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use sqlx::{Connection, Executor, PgPool, Postgres, Transaction};
+use tera::Tera;
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    clock::Clock,
+    configuration::{Settings, SubscriptionTokenTtl},
+    domain::{Email, NewSubscriber, SubscriberName, SubscriberStatus},
+    email_client::EmailClient,
+    issue_delivery_worker::{retry_delay, ExecutionOutcome},
+    routes::{send_confirmation_email, store_token},
+    startup::{get_connection_pool, load_templates},
+};
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Everything needed to send confirmation emails.
+pub struct ConfirmationMailer<'a> {
+    pub email_client: &'a EmailClient,
+    pub templates: &'a Tera,
+    pub base_url: &'a str,
+    pub token_ttl: &'a SubscriptionTokenTtl,
+    pub clock: &'a Clock,
+}
+
+struct ConfirmationTask {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    n_retries: i16,
+}
+
+/// Queues the confirmation email of a subscriber added in bulk, e.g. by an
+/// import, so that it is sent by the worker rather than by the request.
+#[tracing::instrument(name = "Queueing a confirmation email", skip(executor))]
+pub async fn enqueue_confirmation_email(
+    executor: impl sqlx::PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscriber_id, next_attempt_at)
+        VALUES ($1, now())
+        ON CONFLICT (subscriber_id) DO NOTHING
+        "#,
+        subscriber_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn run_confirmation_worker_until_stopped(
+    config: Settings,
+    clock: Clock,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&config.database);
+    let max_attempts = config.email.max_attempts;
+    let token_ttl = config.application.subscription_token_ttl();
+    let email_client = config.email.client();
+    let templates = load_templates();
+    let mailer = ConfirmationMailer {
+        email_client: &email_client,
+        templates: &templates,
+        base_url: &config.application.base_url.0,
+        token_ttl: &token_ttl,
+        clock: &clock,
+    };
+
+    while !shutdown.is_cancelled() {
+        let pause = match try_send_confirmation(&connection_pool, &mailer, max_attempts).await {
+            Ok(ExecutionOutcome::EmptyQueue) => Duration::from_secs(10),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Err(_) => Duration::from_secs(1),
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+    connection_pool.close().await;
+
+    Ok(())
+}
+
+/// Sends one queued confirmation email, with a fresh token. Subscribers who
+/// confirmed or left in the meantime are skipped.
+#[tracing::instrument(
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty, n_retries = tracing::field::Empty),
+    err
+)]
+pub async fn try_send_confirmation(
+    pool: &PgPool,
+    mailer: &ConfirmationMailer<'_>,
+    max_attempts: u16,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("subscriber_id", display(task.subscriber_id))
+        .record("n_retries", task.n_retries);
+
+    if task.status != SubscriberStatus::PendingConfirmation.to_string() {
+        tracing::info!("Skipping a subscriber who is no longer pending confirmation");
+        delete_task(&mut transaction, task.subscriber_id).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let subscriber = match Email::parse(task.email.clone())
+        .and_then(|email| Ok((email, SubscriberName::parse(task.name.clone())?)))
+    {
+        Ok((email, name)) => NewSubscriber { email, name },
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a pending subscriber. Their stored contact details are invalid",
+            );
+            delete_task(&mut transaction, task.subscriber_id).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    // The token is only kept if the email carrying it goes out
+    let mut token_transaction = transaction.begin().await?;
+    let token = store_token(
+        &mut token_transaction,
+        &task.subscriber_id,
+        mailer.token_ttl,
+        mailer.clock,
+    )
+    .await?;
+    let outcome = send_confirmation_email(
+        mailer.email_client,
+        mailer.templates,
+        &subscriber,
+        mailer.base_url,
+        &token,
+    )
+    .await;
+    match outcome {
+        Ok(()) => {
+            token_transaction.commit().await?;
+            delete_task(&mut transaction, task.subscriber_id).await?;
+        }
+        Err(e) => {
+            token_transaction.rollback().await?;
+            if (task.n_retries + 1) as u16 >= max_attempts {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email. Giving up.",
+                );
+                delete_task(&mut transaction, task.subscriber_id).await?;
+            } else {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email. Scheduling a retry.",
+                );
+                reschedule_task(&mut transaction, &task).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, ConfirmationTask)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        ConfirmationTask,
+        r#"
+        SELECT q.subscriber_id, s.email, s.name, s.status, q.n_retries
+        FROM confirmation_email_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        WHERE q.next_attempt_at <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    transaction
+        .execute(sqlx::query!(
+            "DELETE FROM confirmation_email_queue WHERE subscriber_id = $1",
+            subscriber_id
+        ))
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &ConfirmationTask,
+) -> Result<(), anyhow::Error> {
+    let delay = retry_delay(task.n_retries);
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE confirmation_email_queue
+            SET
+                n_retries = n_retries + 1,
+                next_attempt_at = now() + make_interval(secs => $2)
+            WHERE subscriber_id = $1
+            "#,
+            task.subscriber_id,
+            delay.as_secs_f64()
+        ))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,280 @@
+use std::future::{ready, Ready};
+
+use actix_multipart::Multipart;
+use actix_web::{
+    body::MessageBody,
+    cookie::{Cookie, SameSite},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{self, PayloadError},
+    http::{header, Method},
+    middleware::Next,
+    web, FromRequest, HttpMessage, HttpRequest, ResponseError,
+};
+use futures_util::{stream, StreamExt};
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::{configuration::ApplicationBaseUrl, utils::error_chain_fmt};
+
+const CSRF_COOKIE: &str = "csrf_token";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+const URLENCODED_FORM: &str = "application/x-www-form-urlencoded";
+const MULTIPART_FORM: &str = "multipart/form-data";
+/// Tokens are 32 random bytes, hex encoded.
+const MAX_TOKEN_LENGTH: usize = 64;
+/// File uploads are buffered to look for the token, so their size is capped.
+const MAX_MULTIPART_BODY: usize = 4 * 1024 * 1024;
+
+/// Paths that accept cross-site POSTs by design. `/unsubscribe` is called by
+/// mail clients (RFC 8058 one-click) and is already protected by a signed link.
+const EXEMPT_PATHS: &[&str] = &["/unsubscribe"];
+
+/// The anti-forgery token of the current visitor, to be rendered as a hidden
+/// `csrf_token` field in every form.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    fn generate() -> Self {
+        Self(hex::encode(rand::thread_rng().gen::<[u8; 32]>()))
+    }
+
+    fn matches(&self, candidate: &str) -> bool {
+        // Constant time comparison, the token is a secret
+        self.0.len() == candidate.len()
+            && self
+                .0
+                .bytes()
+                .zip(candidate.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl AsRef<str> for CsrfToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CsrfToken, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req
+            .extensions()
+            .get::<CsrfToken>()
+            .cloned()
+            .ok_or_else(|| crate::utils::e500("The CSRF middleware is not enabled"));
+        ready(token)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum CsrfError {
+    #[error("Cross-site requests are not allowed.")]
+    CrossSiteRequest,
+
+    #[error("The CSRF token is missing.")]
+    MissingToken,
+
+    #[error("The CSRF token is invalid.")]
+    InvalidToken,
+
+    #[error("Failed to read the request body.")]
+    InvalidBody(#[source] actix_web::Error),
+}
+
+impl std::fmt::Debug for CsrfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for CsrfError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrfFormField {
+    csrf_token: Option<String>,
+}
+
+/// Double-submit cookie protection: every visitor gets a random token in a
+/// `SameSite=Strict` cookie, and state-changing form submissions must echo it
+/// back, either in a `csrf_token` form field or in the `X-CSRF-Token` header.
+///
+/// Browsers also tell us where a request comes from through `Sec-Fetch-Site`
+/// and `Origin`, so cross-site requests are rejected before looking at the token.
+pub async fn csrf_protection(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let cookie_token = req
+        .cookie(CSRF_COOKIE)
+        .map(|cookie| CsrfToken(cookie.value().to_string()))
+        .filter(|token| !token.0.is_empty());
+    let is_new_token = cookie_token.is_none();
+    let token = cookie_token.unwrap_or_else(CsrfToken::generate);
+
+    if is_unsafe(req.method()) && !EXEMPT_PATHS.contains(&req.path()) {
+        check_origin(&req)?;
+        if is_form_submission(&req) {
+            // Without the cookie there is nothing to compare the submitted token to
+            if is_new_token {
+                return Err(CsrfError::MissingToken.into());
+            }
+            let candidate = submitted_token(&mut req).await?;
+            if !token.matches(&candidate) {
+                return Err(CsrfError::InvalidToken.into());
+            }
+        }
+    }
+
+    req.extensions_mut().insert(token.clone());
+    let mut response = next.call(req).await?;
+
+    if is_new_token {
+        let cookie = Cookie::build(CSRF_COOKIE, token.0)
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .finish();
+        response.response_mut().add_cookie(&cookie)?;
+    }
+
+    Ok(response)
+}
+
+fn is_unsafe(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Only the requests an HTML form (or a "simple" cross-origin request) can
+/// produce need a token: anything else, e.g. the JSON API or a `DELETE`,
+/// requires a CORS preflight that we never grant.
+fn is_form_submission(req: &ServiceRequest) -> bool {
+    if req.method() != Method::POST {
+        return false;
+    }
+    match req.mime_type() {
+        Ok(Some(mime)) => matches!(
+            mime.essence_str(),
+            URLENCODED_FORM | MULTIPART_FORM | "text/plain"
+        ),
+        // Err(_) is a malformed Content-Type, which a browser could still send
+        Ok(None) | Err(_) => true,
+    }
+}
+
+fn check_origin(req: &ServiceRequest) -> Result<(), CsrfError> {
+    if let Some(fetch_site) = req.headers().get("Sec-Fetch-Site") {
+        return match fetch_site.as_bytes() {
+            b"same-origin" | b"none" => Ok(()),
+            _ => Err(CsrfError::CrossSiteRequest),
+        };
+    }
+
+    if let Some(origin) = req.headers().get(header::ORIGIN) {
+        let base_url = req
+            .app_data::<web::Data<ApplicationBaseUrl>>()
+            .map(|base_url| base_url.0.trim_end_matches('/').to_string());
+        if base_url.as_deref().map(str::as_bytes) != Some(origin.as_bytes()) {
+            return Err(CsrfError::CrossSiteRequest);
+        }
+    }
+
+    Ok(())
+}
+
+async fn submitted_token(req: &mut ServiceRequest) -> Result<String, CsrfError> {
+    if let Some(header_value) = req.headers().get(CSRF_HEADER) {
+        return header_value
+            .to_str()
+            .map(str::to_string)
+            .map_err(|_| CsrfError::InvalidToken);
+    }
+
+    let essence = req
+        .mime_type()
+        .ok()
+        .flatten()
+        .map(|mime| mime.essence_str().to_string());
+    match essence.as_deref() {
+        Some(URLENCODED_FORM) => urlencoded_token(req).await,
+        Some(MULTIPART_FORM) => multipart_token(req).await,
+        _ => Err(CsrfError::MissingToken),
+    }
+}
+
+async fn urlencoded_token(req: &mut ServiceRequest) -> Result<String, CsrfError> {
+    // The handler still needs the body, so it is put back once read
+    let body = req
+        .extract::<web::Bytes>()
+        .await
+        .map_err(CsrfError::InvalidBody)?;
+    let field = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| web::Query::<CsrfFormField>::from_query(body).ok())
+        .and_then(|form| form.into_inner().csrf_token);
+    req.set_payload(Payload::from(body));
+
+    field.ok_or(CsrfError::MissingToken)
+}
+
+async fn multipart_token(req: &mut ServiceRequest) -> Result<String, CsrfError> {
+    let body = req
+        .extract::<web::Payload>()
+        .await
+        .map_err(CsrfError::InvalidBody)?
+        .to_bytes_limited(MAX_MULTIPART_BODY)
+        .await
+        .map_err(|e| CsrfError::InvalidBody(error::ErrorPayloadTooLarge(e)))?
+        .map_err(CsrfError::InvalidBody)?;
+    req.set_payload(Payload::from(body.clone()));
+
+    let mut multipart = Multipart::new(
+        req.headers(),
+        stream::once(ready(Ok::<_, PayloadError>(body))),
+    );
+    while let Some(field) = multipart.next().await {
+        let mut field = field.map_err(|e| CsrfError::InvalidBody(e.into()))?;
+        if field.name() == Some("csrf_token") {
+            let value = field
+                .bytes(MAX_TOKEN_LENGTH)
+                .await
+                .map_err(|_| CsrfError::InvalidToken)?
+                .map_err(|e| CsrfError::InvalidBody(e.into()))?;
+            return String::from_utf8(value.to_vec()).map_err(|_| CsrfError::InvalidToken);
+        }
+    }
+
+    Err(CsrfError::MissingToken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CsrfToken;
+
+    #[test]
+    fn a_token_matches_itself() {
+        let token = CsrfToken::generate();
+        assert!(token.matches(&token.0.clone()));
+    }
+
+    #[test]
+    fn a_token_does_not_match_another_token() {
+        let token = CsrfToken::generate();
+        assert!(!token.matches(&CsrfToken::generate().0));
+        assert!(!token.matches(&token.0[1..]));
+        assert!(!token.matches(""));
+    }
+}
@@ -0,0 +1,13 @@
+/// A change in what a subscriber agreed to, kept as a history next to their
+/// current [`SubscriberStatus`](super::SubscriberStatus).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, strum_macros::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ConsentEvent {
+    /// Filled in the subscription form.
+    Subscribed,
+    /// Added through an import, having opted in somewhere else.
+    Imported,
+    /// Followed the link in their confirmation email.
+    Confirmed,
+    Unsubscribed,
+}
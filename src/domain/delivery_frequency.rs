@@ -0,0 +1,111 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Digests go out at this hour of the day, UTC.
+const DIGEST_HOUR: u32 = 8;
+
+/// How often a subscriber receives issues. Issues published in between are
+/// held back and sent together, as a single digest email.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum_macros::Display,
+    strum_macros::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DeliveryFrequency {
+    /// Every issue as soon as it is published.
+    #[default]
+    Immediate,
+    /// Every morning.
+    Daily,
+    /// Every Monday morning.
+    Weekly,
+}
+
+impl DeliveryFrequency {
+    /// When an issue published at `now` is to be sent.
+    pub fn next_delivery_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now
+            .date_naive()
+            .and_hms_opt(DIGEST_HOUR, 0, 0)
+            .expect("The digest hour is valid")
+            .and_utc();
+        let next_daily = if today > now {
+            today
+        } else {
+            today + Duration::days(1)
+        };
+
+        match self {
+            Self::Immediate => now,
+            Self::Daily => next_daily,
+            Self::Weekly => {
+                let days_to_monday = (7 - next_daily.weekday().num_days_from_monday()) % 7;
+                next_daily + Duration::days(days_to_monday.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryFrequency;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // March 2024 starts on a Friday, the 4th is a Monday
+        Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn immediate_deliveries_are_not_delayed() {
+        assert_eq!(
+            DeliveryFrequency::Immediate.next_delivery_at(at(5, 13, 37)),
+            at(5, 13, 37)
+        );
+    }
+
+    #[test]
+    fn daily_digests_go_out_the_next_morning() {
+        assert_eq!(
+            DeliveryFrequency::Daily.next_delivery_at(at(5, 13, 37)),
+            at(6, 8, 0)
+        );
+        assert_eq!(
+            DeliveryFrequency::Daily.next_delivery_at(at(5, 8, 0)),
+            at(6, 8, 0)
+        );
+    }
+
+    #[test]
+    fn daily_digests_go_out_the_same_morning_before_the_digest_time() {
+        assert_eq!(
+            DeliveryFrequency::Daily.next_delivery_at(at(5, 7, 59)),
+            at(5, 8, 0)
+        );
+    }
+
+    #[test]
+    fn weekly_digests_go_out_on_the_next_monday_morning() {
+        assert_eq!(
+            DeliveryFrequency::Weekly.next_delivery_at(at(6, 13, 37)),
+            at(11, 8, 0)
+        );
+        assert_eq!(
+            DeliveryFrequency::Weekly.next_delivery_at(at(3, 23, 0)),
+            at(4, 8, 0)
+        );
+        assert_eq!(
+            DeliveryFrequency::Weekly.next_delivery_at(at(4, 8, 0)),
+            at(11, 8, 0)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+const MAX_LENGTH: usize = 64;
+
+/// Short identifier of a mailing list, as found in forms and in the API:
+/// lowercase ASCII letters, digits and inner hyphens, e.g. `product-updates`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn parse(s: String) -> Result<Self, String> {
+        let has_valid_characters = s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        let has_outer_hyphen = s.starts_with('-') || s.ends_with('-');
+
+        if s.is_empty() || s.len() > MAX_LENGTH || !has_valid_characters || has_outer_hyphen {
+            Err(format!("{s} is not a valid list name"))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl std::fmt::Display for ListSlug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListSlug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_letters_digits_and_hyphens_are_valid() {
+        assert_ok!(ListSlug::parse("product-updates-2024".to_string()));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(ListSlug::parse("".to_string()));
+    }
+
+    #[test]
+    fn a_65_character_long_slug_is_rejected() {
+        assert_ok!(ListSlug::parse("a".repeat(64)));
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn uppercase_letters_spaces_and_punctuation_are_rejected() {
+        for slug in [
+            "Blog",
+            "engineering blog",
+            "blog_posts",
+            "blog.posts",
+            "blög",
+        ] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn leading_or_trailing_hyphens_are_rejected() {
+        assert_err!(ListSlug::parse("-blog".to_string()));
+        assert_err!(ListSlug::parse("blog-".to_string()));
+    }
+}
@@ -1,9 +1,17 @@
+mod consent_event;
+mod delivery_frequency;
+mod list_slug;
 mod new_subscriber;
+mod send_time;
 mod subscriber_email;
 mod subscriber_name;
 mod subscriber_status;
 
+pub use consent_event::ConsentEvent;
+pub use delivery_frequency::DeliveryFrequency;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use send_time::SendTime;
 pub use subscriber_email::Email;
 pub use subscriber_name::SubscriberName;
 pub use subscriber_status::SubscriberStatus;
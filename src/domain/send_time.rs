@@ -0,0 +1,126 @@
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Formats accepted for a local date and time, the second one being what an
+/// HTML `datetime-local` input sends.
+const LOCAL_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+/// When a scheduled newsletter issue goes out. Authors give it either as an
+/// RFC 3339 timestamp, which carries its own offset, or as a local date and
+/// time along with an IANA timezone (`Europe/Paris`), UTC if none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendTime(DateTime<Utc>);
+
+impl SendTime {
+    pub fn parse(at: &str, timezone: Option<&str>, now: DateTime<Utc>) -> Result<Self, String> {
+        let timezone: Tz = match timezone {
+            Some(timezone) => timezone
+                .parse()
+                .map_err(|_| format!("{timezone} is not a known timezone"))?,
+            None => Tz::UTC,
+        };
+
+        let send_time = match DateTime::parse_from_rfc3339(at) {
+            Ok(send_time) => send_time.with_timezone(&Utc),
+            Err(_) => {
+                let local = LOCAL_FORMATS
+                    .iter()
+                    .find_map(|format| NaiveDateTime::parse_from_str(at, format).ok())
+                    .ok_or_else(|| format!("{at} is not a valid date and time"))?;
+                match timezone.from_local_datetime(&local) {
+                    LocalResult::Single(send_time) => send_time.with_timezone(&Utc),
+                    // When clocks go back, the first time round
+                    LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+                    LocalResult::None => {
+                        return Err(format!("{at} does not exist in {timezone}, clocks skip it"))
+                    }
+                }
+            }
+        };
+
+        if send_time <= now {
+            return Err("The send time must be in the future.".into());
+        }
+
+        Ok(Self(send_time))
+    }
+}
+
+impl From<SendTime> for DateTime<Utc> {
+    fn from(value: SendTime) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendTime;
+    use chrono::{DateTime, TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    fn parse(at: &str, timezone: Option<&str>) -> Result<DateTime<Utc>, String> {
+        SendTime::parse(at, timezone, now()).map(Into::into)
+    }
+
+    #[test]
+    fn a_timestamp_with_an_offset_is_taken_as_is() {
+        let send_time = assert_ok!(parse("2024-03-20T09:00:00+01:00", None));
+        assert_eq!(
+            send_time,
+            Utc.with_ymd_and_hms(2024, 3, 20, 8, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_local_time_is_read_in_utc_by_default() {
+        let send_time = assert_ok!(parse("2024-03-20T09:00", None));
+        assert_eq!(
+            send_time,
+            Utc.with_ymd_and_hms(2024, 3, 20, 9, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_local_time_follows_daylight_saving_time_in_its_timezone() {
+        // Paris is on UTC+1 in winter and UTC+2 from March 31st
+        let winter = assert_ok!(parse("2024-03-20T09:00:00", Some("Europe/Paris")));
+        let summer = assert_ok!(parse("2024-04-20T09:00:00", Some("Europe/Paris")));
+
+        assert_eq!(winter, Utc.with_ymd_and_hms(2024, 3, 20, 8, 0, 0).unwrap());
+        assert_eq!(summer, Utc.with_ymd_and_hms(2024, 4, 20, 7, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn a_local_time_skipped_by_the_clocks_is_rejected() {
+        assert_err!(parse("2024-03-31T02:30", Some("Europe/Paris")));
+    }
+
+    #[test]
+    fn a_local_time_repeated_by_the_clocks_is_the_first_one() {
+        let send_time = assert_ok!(parse("2024-10-27T02:30", Some("Europe/Paris")));
+        assert_eq!(
+            send_time,
+            Utc.with_ymd_and_hms(2024, 10, 27, 0, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_timezones_are_rejected() {
+        assert_err!(parse("2024-03-20T09:00", Some("Europe/Atlantis")));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(parse("next tuesday", None));
+    }
+
+    #[test]
+    fn past_times_are_rejected() {
+        assert_err!(parse("2024-03-01T12:00:00Z", None));
+        assert_err!(parse("2024-02-01T09:00", None));
+    }
+}
@@ -1,6 +1,9 @@
-#[derive(sqlx::Type, Debug, strum_macros::Display)]
+#[derive(sqlx::Type, Debug, Clone, Copy, strum_macros::Display, strum_macros::EnumString)]
 #[strum(serialize_all = "snake_case")]
 pub enum SubscriberStatus {
     PendingConfirmation,
     Ok,
+    Unsubscribed,
+    /// Anonymized at the subscriber's request, the row only remains for statistics.
+    Erased,
 }
@@ -0,0 +1,131 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// An issue being written. Nothing is checked until it is published, so that
+/// half-written drafts can be saved.
+#[derive(Debug, Serialize)]
+pub struct Draft {
+    pub draft_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Fetching the drafts", skip(pool))]
+pub async fn all_drafts(pool: &PgPool) -> Result<Vec<Draft>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT draft_id, title, text_content, html_content, updated_at
+        FROM newsletter_drafts
+        ORDER BY updated_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the drafts")?;
+
+    Ok(drafts)
+}
+
+#[tracing::instrument(name = "Fetching a draft", skip(pool))]
+pub async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT draft_id, title, text_content, html_content, updated_at
+        FROM newsletter_drafts
+        WHERE draft_id = $1
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the draft")?;
+
+    Ok(draft)
+}
+
+#[tracing::instrument(name = "Creating a draft", skip(pool, text_content, html_content))]
+pub async fn create_draft(
+    pool: &PgPool,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let draft_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_drafts (
+            draft_id,
+            title,
+            text_content,
+            html_content,
+            created_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $5)
+        "#,
+        draft_id,
+        title,
+        text_content,
+        html_content,
+        now
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert the draft")?;
+
+    Ok(draft_id)
+}
+
+/// Returns whether there was such a draft.
+#[tracing::instrument(name = "Updating a draft", skip(pool, text_content, html_content))]
+pub async fn update_draft(
+    pool: &PgPool,
+    draft_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_drafts
+        SET title = $2, text_content = $3, html_content = $4, updated_at = $5
+        WHERE draft_id = $1
+        "#,
+        draft_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the draft")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+/// Meant to run in the transaction publishing the draft, so that it only goes
+/// away once the issue made from it is stored.
+#[tracing::instrument(name = "Deleting a draft", skip(executor))]
+pub async fn delete_draft(
+    executor: impl PgExecutor<'_>,
+    draft_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_drafts WHERE draft_id = $1",
+        draft_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
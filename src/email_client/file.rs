@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{build_mime_message, EmailMessage, EmailTransport, SendEmailError};
+
+/// Writes every email as an RFC 5322 `.eml` file into a local directory
+/// instead of delivering it, which is handy during development.
+pub struct FileTransport {
+    directory: PathBuf,
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+
+        Ok(Self {
+            transport: AsyncFileTransport::new(&directory),
+            directory,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
+        let message = build_mime_message(message)?;
+        let id = self.transport.send(message).await?;
+        tracing::debug!(message_id = %id, "Wrote email to the file sink");
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        let metadata = std::fs::metadata(&self.directory)
+            .map_err(|e| SendEmailError::Unavailable(e.into()))?;
+        if !metadata.is_dir() || metadata.permissions().readonly() {
+            return Err(SendEmailError::Unavailable(anyhow::anyhow!(
+                "{} is not a writable directory",
+                self.directory.display()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use fake::{faker::internet::en::SafeEmail, Fake};
+
+    use crate::{
+        domain::Email,
+        email_client::{EmailClient, EmailTransport},
+    };
+
+    use super::FileTransport;
+
+    fn email() -> Email {
+        Email::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_directory() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(email(), FileTransport::new(&directory).unwrap());
+        let recipient = email();
+
+        let result = email_client
+            .send_email(&recipient, "Newsletter title", "<p>HTML</p>", "Plain text")
+            .await;
+
+        assert_ok!(result);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("Subject: Newsletter title"));
+        assert!(contents.contains(&format!("To: {}", recipient)));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ping_fails_once_the_directory_is_gone() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport = FileTransport::new(&directory).unwrap();
+        assert_ok!(transport.ping().await);
+
+        std::fs::remove_dir(&directory).unwrap();
+
+        assert_err!(transport.ping().await);
+    }
+}
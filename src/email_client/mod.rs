@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use lettre::message::header::{HeaderName, HeaderValue};
+
+use crate::{domain::Email, metrics::record_email_outcome, utils::error_chain_fmt};
+
+mod file;
+mod postmark;
+mod smtp;
+
+pub use file::FileTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::SmtpTransport;
+
+/// A message ready to be handed over to an [`EmailTransport`].
+pub struct EmailMessage<'a> {
+    pub from: &'a Email,
+    pub to: &'a Email,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError>;
+
+    /// Checks that emails could be sent right now, without sending any.
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailClient {
+    sender: Email,
+    transport: Arc<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(sender: Email, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Arc::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_body, text_body, &[])
+            .await
+    }
+
+    /// Same as [`EmailClient::send_email`], with extra headers (e.g. `List-Unsubscribe`)
+    /// added to the message.
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        let message = EmailMessage {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body,
+            text_body,
+            headers,
+        };
+
+        let outcome = self.transport.send(&message).await;
+        record_email_outcome(&outcome);
+        outcome
+    }
+
+    pub async fn ping(&self) -> Result<(), SendEmailError> {
+        self.transport.ping().await
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error("Failed to build the email message")]
+    InvalidMessage(#[source] anyhow::Error),
+
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+
+    #[error(transparent)]
+    FileError(#[from] lettre::transport::file::Error),
+
+    #[error("The email transport is unavailable")]
+    Unavailable(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Builds an RFC 5322 message with a plain text and an HTML alternative,
+/// shared by the transports that don't talk to an HTTP API.
+fn build_mime_message(message: &EmailMessage<'_>) -> Result<lettre::Message, SendEmailError> {
+    let from = message
+        .from
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::InvalidMessage(anyhow::Error::new(e)))?;
+    let to = message
+        .to
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::InvalidMessage(anyhow::Error::new(e)))?;
+
+    let mut mime_message = lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(message.subject)
+        .multipart(lettre::message::MultiPart::alternative_plain_html(
+            message.text_body.to_string(),
+            message.html_body.to_string(),
+        ))
+        .map_err(|e| SendEmailError::InvalidMessage(anyhow::Error::new(e)))?;
+
+    for (name, value) in message.headers {
+        let name = HeaderName::new_from_ascii(name.to_string())
+            .map_err(|e| SendEmailError::InvalidMessage(anyhow::Error::new(e)))?;
+        mime_message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(name, value.to_string()));
+    }
+
+    Ok(mime_message)
+}
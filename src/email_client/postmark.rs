@@ -1,14 +1,15 @@
 use std::time::Duration;
 
-use crate::domain::Email;
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
 
-#[derive(Clone)]
-pub struct EmailClient {
+use super::{EmailMessage, EmailTransport, SendEmailError};
+use crate::telemetry::trace_context_headers;
+
+/// Sends emails through Postmark's `/email` JSON API.
+pub struct PostmarkTransport {
     base_url: String,
     http_client: reqwest::Client,
-    sender: Email,
     token: Secret<String>,
     timeout: Duration,
 }
@@ -21,6 +22,15 @@ struct SendEmailRequestBody<'a> {
     subject: &'a str,
     text_body: &'a str,
     html_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
 }
 
 /* #[derive(Deserialize)]
@@ -33,37 +43,39 @@ struct EmailSubmissionResult {
     submitted_at: DateTime<chrono::Utc>,
 } */
 
-impl EmailClient {
-    pub fn new(base_url: String, sender: Email, token: Secret<String>, timeout: Duration) -> Self {
+impl PostmarkTransport {
+    pub fn new(base_url: String, token: Secret<String>, timeout: Duration) -> Self {
         Self {
             http_client: reqwest::Client::new(),
             base_url,
-            sender,
             token,
             timeout,
         }
     }
+}
 
-    pub async fn send_email(
-        &self,
-        recipient: &Email,
-        subject: &str,
-        html_body: &str,
-        text_body: &str,
-    ) -> Result<(), reqwest::Error> {
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
         let url = format!("{}/email", self.base_url);
         let body = SendEmailRequestBody {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            text_body,
-            subject,
-            html_body,
+            from: message.from.as_ref(),
+            to: message.to.as_ref(),
+            text_body: message.text_body,
+            subject: message.subject,
+            html_body: message.html_body,
+            headers: message
+                .headers
+                .iter()
+                .map(|&(name, value)| PostmarkHeader { name, value })
+                .collect(),
         };
 
         let _ = self
             .http_client
             .post(url)
             .timeout(self.timeout)
+            .headers(trace_context_headers())
             .json(&body)
             .header("X-Postmark-Server-Token", self.token.expose_secret())
             .send()
@@ -72,6 +84,23 @@ impl EmailClient {
 
         Ok(())
     }
+
+    /// Fetches the details of the server the token belongs to, which checks
+    /// both that Postmark is reachable and that the token is valid.
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        let url = format!("{}/server", self.base_url);
+        self.http_client
+            .get(url)
+            .timeout(self.timeout)
+            .headers(trace_context_headers())
+            .header("Accept", "application/json")
+            .header("X-Postmark-Server-Token", self.token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -88,9 +117,9 @@ mod tests {
         Mock, MockServer, ResponseTemplate,
     };
 
-    use crate::domain::Email;
+    use crate::{domain::Email, email_client::EmailClient};
 
-    use super::EmailClient;
+    use super::PostmarkTransport;
 
     struct SendEmailBodyMatcher;
 
@@ -117,10 +146,12 @@ mod tests {
 
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
-            base_url,
             email(),
-            secrecy::Secret::new(fake::Faker.fake()),
-            Duration::from_millis(200),
+            PostmarkTransport::new(
+                base_url,
+                secrecy::Secret::new(fake::Faker.fake()),
+                Duration::from_millis(200),
+            ),
         )
     }
 
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{build_mime_message, EmailMessage, EmailTransport, SendEmailError};
+
+/// Sends emails through an SMTP relay, upgrading the connection with STARTTLS
+/// unless it is explicitly disabled.
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, Secret<String>)>,
+        starttls: bool,
+        timeout: Duration,
+    ) -> Result<Self, SendEmailError> {
+        let mut builder = if starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        }
+        .port(port)
+        .timeout(Some(timeout));
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
+        let message = build_mime_message(message)?;
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        if !self.transport.test_connection().await? {
+            return Err(SendEmailError::Unavailable(anyhow::anyhow!(
+                "The SMTP server did not answer to NOOP"
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditAction},
+    configuration::SuppressionSalt,
+    domain::{Email, SubscriberStatus},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErasureMode {
+    /// Every row about the subscriber is deleted.
+    #[default]
+    Delete,
+    /// Their rows are kept without their email address and name, so that
+    /// delivery figures don't change.
+    Anonymize,
+}
+
+impl ErasureMode {
+    fn audit_action(&self) -> AuditAction {
+        match self {
+            Self::Delete => AuditAction::SubscriberDeleted,
+            Self::Anonymize => AuditAction::SubscriberAnonymized,
+        }
+    }
+}
+
+/// Keyed hash of an email address, for the suppression list. Addresses that
+/// only differ by case or surrounding spaces share the same hash.
+pub fn suppression_hash(salt: &SuppressionSalt, email: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(salt.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether the address belongs to a subscriber who asked to be erased.
+#[tracing::instrument(name = "Checking the suppression list", skip_all)]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    salt: &SuppressionSalt,
+    email: &Email,
+) -> Result<bool, sqlx::Error> {
+    let suppressed = sqlx::query_scalar!(
+        "SELECT EXISTS (SELECT 1 FROM suppressed_emails WHERE email_hash = $1)",
+        suppression_hash(salt, email.as_ref())
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(suppressed.unwrap_or(false))
+}
+
+/// Removes a subscriber's personal data from every table and adds their
+/// address to the suppression list, so that it is not imported again.
+///
+/// Every subscriber whose address only differs by case is erased along with
+/// it. The address is suppressed even when no subscriber uses it. Returns the
+/// ids of the erased subscribers.
+#[tracing::instrument(name = "Erasing a subscriber", skip(pool, salt, email), err)]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    salt: &SuppressionSalt,
+    email: &Email,
+    mode: ErasureMode,
+    user_id: Option<Uuid>,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    // Matched the way the suppression list hashes it, so that a request in
+    // another case than the stored address still erases it
+    let address = email.as_ref().trim();
+
+    let subscribers = sqlx::query!(
+        "SELECT id, email FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE",
+        address
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .context("Failed to look up the subscribers")?;
+
+    tx.execute(sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = lower($1)",
+        address
+    ))
+    .await
+    .context("Failed to delete the queued deliveries")?;
+
+    match mode {
+        ErasureMode::Delete => {
+            tx.execute(sqlx::query!(
+                "DELETE FROM issue_deliveries WHERE lower(subscriber_email) = lower($1)",
+                address
+            ))
+            .await
+            .context("Failed to delete the deliveries")?;
+            tx.execute(sqlx::query!(
+                r#"
+                DELETE FROM issue_delivery_dead_letters
+                WHERE lower(subscriber_email) = lower($1)
+                "#,
+                address
+            ))
+            .await
+            .context("Failed to delete the failed deliveries")?;
+            // Tokens, consent events and list memberships go along with it
+            tx.execute(sqlx::query!(
+                "DELETE FROM subscriptions WHERE lower(email) = lower($1)",
+                address
+            ))
+            .await
+            .context("Failed to delete the subscriber")?;
+        }
+        ErasureMode::Anonymize => {
+            for subscriber in &subscribers {
+                // Addresses are unique, so is the placeholder
+                let placeholder = format!("{}@erased.invalid", subscriber.id);
+                anonymize_deliveries(&mut tx, &subscriber.email, &placeholder).await?;
+                tx.execute(sqlx::query!(
+                    "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+                    subscriber.id
+                ))
+                .await
+                .context("Failed to delete the subscription tokens")?;
+                tx.execute(sqlx::query!(
+                    "DELETE FROM confirmation_email_queue WHERE subscriber_id = $1",
+                    subscriber.id
+                ))
+                .await
+                .context("Failed to delete the queued confirmation email")?;
+                tx.execute(sqlx::query!(
+                    "DELETE FROM list_memberships WHERE subscriber_id = $1",
+                    subscriber.id
+                ))
+                .await
+                .context("Failed to delete the list memberships")?;
+                tx.execute(sqlx::query!(
+                    "UPDATE subscriptions SET email = $2, name = '', status = $3 WHERE id = $1",
+                    subscriber.id,
+                    placeholder,
+                    SubscriberStatus::Erased.to_string()
+                ))
+                .await
+                .context("Failed to anonymize the subscriber")?;
+            }
+            // Deliveries to an address no subscriber uses anymore
+            let former_addresses = sqlx::query_scalar!(
+                r#"
+                SELECT subscriber_email AS "subscriber_email!" FROM issue_deliveries
+                WHERE lower(subscriber_email) = lower($1)
+                UNION
+                SELECT subscriber_email FROM issue_delivery_dead_letters
+                WHERE lower(subscriber_email) = lower($1)
+                "#,
+                address
+            )
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to look up the former addresses")?;
+            for former_address in &former_addresses {
+                let placeholder = format!("{}@erased.invalid", Uuid::new_v4());
+                anonymize_deliveries(&mut tx, former_address, &placeholder).await?;
+            }
+        }
+    }
+
+    tx.execute(sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (email_hash, suppressed_at)
+        VALUES ($1, $2)
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        suppression_hash(salt, email.as_ref()),
+        Utc::now()
+    ))
+    .await
+    .context("Failed to add the address to the suppression list")?;
+    let subscriber_ids = subscribers
+        .into_iter()
+        .map(|subscriber| subscriber.id)
+        .collect::<Vec<_>>();
+    if subscriber_ids.is_empty() {
+        record_audit_event(&mut *tx, user_id, mode.audit_action(), None)
+            .await
+            .context("Failed to record the audit event")?;
+    }
+    for subscriber_id in &subscriber_ids {
+        record_audit_event(&mut *tx, user_id, mode.audit_action(), Some(*subscriber_id))
+            .await
+            .context("Failed to record the audit event")?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(subscriber_ids)
+}
+
+/// Replaces the address on the deliveries made to it.
+async fn anonymize_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    address: &str,
+    placeholder: &str,
+) -> Result<(), anyhow::Error> {
+    tx.execute(sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET subscriber_email = $2
+        WHERE subscriber_email = $1
+        "#,
+        address,
+        placeholder
+    ))
+    .await
+    .context("Failed to anonymize the deliveries")?;
+    tx.execute(sqlx::query!(
+        r#"
+        UPDATE issue_delivery_dead_letters
+        SET subscriber_email = $2, last_error = ''
+        WHERE subscriber_email = $1
+        "#,
+        address,
+        placeholder
+    ))
+    .await
+    .context("Failed to anonymize the failed deliveries")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suppression_hash;
+    use crate::configuration::SuppressionSalt;
+    use secrecy::Secret;
+
+    fn salt(value: &str) -> SuppressionSalt {
+        SuppressionSalt(Secret::new(value.to_string()))
+    }
+
+    #[test]
+    fn the_hash_ignores_case_and_surrounding_spaces() {
+        assert_eq!(
+            suppression_hash(&salt("salt"), "ursula@example.com"),
+            suppression_hash(&salt("salt"), " Ursula@Example.com "),
+        );
+    }
+
+    #[test]
+    fn the_hash_depends_on_the_salt() {
+        assert_ne!(
+            suppression_hash(&salt("salt"), "ursula@example.com"),
+            suppression_hash(&salt("another salt"), "ursula@example.com"),
+        );
+    }
+}
@@ -0,0 +1,50 @@
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn parse(s: String) -> Result<Self, String> {
+        let max_length = 50;
+
+        if s.trim().is_empty() {
+            Err("The idempotency key cannot be empty".to_string())
+        } else if s.len() >= max_length {
+            Err(format!(
+                "The idempotency key must be shorter than {max_length} characters"
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(IdempotencyKey::parse("".to_string()));
+    }
+
+    #[test]
+    fn whitespace_only_keys_are_rejected() {
+        assert_err!(IdempotencyKey::parse("   ".to_string()));
+    }
+
+    #[test]
+    fn a_50_character_long_key_is_rejected() {
+        assert_err!(IdempotencyKey::parse("a".repeat(50)));
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        assert_ok!(IdempotencyKey::parse(uuid::Uuid::new_v4().to_string()));
+    }
+}
@@ -0,0 +1,515 @@
+use std::time::Duration;
+
+use rand::Rng;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{HmacSecret, Settings},
+    domain::{DeliveryFrequency, Email, SubscriberStatus},
+    email_client::EmailClient,
+    issue_renderer::IssueRenderer,
+    startup::{get_connection_pool, load_templates},
+};
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+#[derive(Clone)]
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+pub async fn run_worker_until_stopped(
+    config: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&config.database);
+    let max_attempts = config.email.max_attempts;
+    let email_client = config.email.client();
+    let renderer = IssueRenderer::new(
+        load_templates(),
+        config.application.base_url,
+        HmacSecret(config.application.hmac_secret),
+    );
+
+    worker_loop(
+        &connection_pool,
+        email_client,
+        renderer,
+        max_attempts,
+        shutdown,
+    )
+    .await;
+    connection_pool.close().await;
+
+    Ok(())
+}
+
+/// Delivers tasks until shutdown. A task being delivered is always completed
+/// first, so that no email is left half-sent.
+async fn worker_loop(
+    pool: &PgPool,
+    email_client: EmailClient,
+    renderer: IssueRenderer,
+    max_attempts: u16,
+    shutdown: CancellationToken,
+) {
+    while !shutdown.is_cancelled() {
+        let pause = match try_execute_task(pool, &email_client, &renderer, max_attempts).await {
+            Ok(ExecutionOutcome::EmptyQueue) => Duration::from_secs(10),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Err(_) => Duration::from_secs(1),
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty,
+        n_retries = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    renderer: &IssueRenderer,
+    max_attempts: u16,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email))
+        .record("n_retries", task.n_retries);
+
+    let email = match Email::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid",
+            );
+            delete_task(&mut transaction, &task).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let Some(subscriber) =
+        get_subscribed_subscriber(pool, &email, task.newsletter_issue_id).await?
+    else {
+        tracing::info!("Skipping a subscriber who is no longer subscribed");
+        delete_task(&mut transaction, &task).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    let frequency = subscriber
+        .delivery_frequency
+        .parse::<DeliveryFrequency>()
+        .unwrap_or_default();
+    let mut tasks = vec![task];
+    if frequency != DeliveryFrequency::Immediate {
+        // The other issues held back for the same digest go out with this one
+        let digest_tasks =
+            dequeue_digest_tasks(&mut transaction, &tasks[0], &subscriber.id).await?;
+        tasks.extend(digest_tasks);
+    }
+
+    let issue_ids: Vec<Uuid> = tasks.iter().map(|task| task.newsletter_issue_id).collect();
+    let issues = get_issues(pool, &issue_ids).await?;
+    let issue = match &issues[..] {
+        [issue] => issue.clone(),
+        issues => digest(frequency, issues),
+    };
+    let rendered = renderer.render(&issue.html_content, &issue.text_content, &subscriber.id)?;
+
+    let outcome = email_client
+        .send_email_with_headers(
+            &email,
+            &issue.title,
+            &rendered.html_content,
+            &rendered.text_content,
+            &rendered.headers(),
+        )
+        .await;
+    for task in &tasks {
+        match &outcome {
+            Ok(()) => mark_task_delivered(&mut transaction, task).await?,
+            Err(e) if (task.n_retries + 1) as u16 >= max_attempts => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to deliver issue to a confirmed subscriber. Moving it to the dead letter queue.",
+                );
+                move_task_to_dead_letters(&mut transaction, task, &e.to_string()).await?;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to deliver issue to a confirmed subscriber. Scheduling a retry.",
+                );
+                reschedule_task(&mut transaction, task).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Several issues as a single one, each under its own title.
+fn digest(frequency: DeliveryFrequency, issues: &[NewsletterIssue]) -> NewsletterIssue {
+    let html_content = issues
+        .iter()
+        .map(|issue| {
+            format!(
+                "<h2>{}</h2>\n{}",
+                tera::escape_html(&issue.title),
+                issue.html_content
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n<hr>\n");
+    let text_content = issues
+        .iter()
+        .map(|issue| format!("{}\n\n{}", issue.title, issue.text_content))
+        .collect::<Vec<_>>()
+        .join("\n\n---\n\n");
+
+    NewsletterIssue {
+        title: format!("Your {frequency} digest: {} new issues", issues.len()),
+        text_content,
+        html_content,
+    }
+}
+
+/// Exponential backoff with jitter: half of the delay is fixed, the other half
+/// is random so that retries for a large issue don't all hit the provider at once.
+pub(crate) fn retry_delay(n_retries: i16) -> Duration {
+    let exponent = n_retries.clamp(0, 16) as u32;
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    let half = delay / 2;
+    let jitter = rand::thread_rng().gen_range(Duration::ZERO..=half);
+
+    half + jitter
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let query = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE next_attempt_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    );
+    let task = query.fetch_optional(&mut *transaction).await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+/// Keeps a record of the delivery, as the subscriber may ask what was sent to them.
+#[tracing::instrument(skip_all)]
+async fn mark_task_delivered(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, delivered_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+
+    delete_task(transaction, task).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let delay = retry_delay(task.n_retries);
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            next_attempt_at = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, task))]
+async fn move_task_to_dead_letters(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries + 1,
+        last_error
+    );
+    transaction.execute(query).await?;
+
+    delete_task(transaction, task).await
+}
+
+/// Puts dead-lettered deliveries back on the queue, optionally limited to a
+/// single issue. Returns the number of requeued deliveries.
+#[tracing::instrument(skip(pool))]
+pub async fn requeue_dead_letters(
+    pool: &PgPool,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let query = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            DELETE FROM issue_delivery_dead_letters
+            WHERE $1::uuid IS NULL OR newsletter_issue_id = $1
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email FROM requeued
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id
+    );
+    let n_requeued = transaction.execute(query).await?.rows_affected();
+    transaction.commit().await?;
+
+    Ok(n_requeued)
+}
+
+struct Subscriber {
+    id: Uuid,
+    delivery_frequency: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscribed_subscriber(
+    pool: &PgPool,
+    email: &Email,
+    issue_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    // They may have left the issue's lists or taken a break since it was published
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, delivery_frequency FROM subscriptions
+        WHERE email = $1
+            AND status = $2
+            AND (paused_until IS NULL OR paused_until <= now())
+            AND EXISTS (
+                SELECT 1
+                FROM list_memberships
+                JOIN newsletter_issue_lists USING (list_id)
+                WHERE list_memberships.subscriber_id = subscriptions.id
+                    AND list_memberships.status = $2
+                    AND newsletter_issue_lists.newsletter_issue_id = $3
+            )
+        "#,
+        email.as_ref(),
+        SubscriberStatus::Ok.to_string(),
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subscriber)
+}
+
+/// The subscriber's other due deliveries, for issues they are still
+/// subscribed to.
+#[tracing::instrument(skip_all)]
+async fn dequeue_digest_tasks(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    subscriber_id: &Uuid,
+) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE subscriber_email = $1
+            AND newsletter_issue_id <> $2
+            AND next_attempt_at <= now()
+            AND EXISTS (
+                SELECT 1
+                FROM list_memberships
+                JOIN newsletter_issue_lists USING (list_id)
+                WHERE list_memberships.subscriber_id = $3
+                    AND list_memberships.status = $4
+                    AND newsletter_issue_lists.newsletter_issue_id
+                        = issue_delivery_queue.newsletter_issue_id
+            )
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        task.subscriber_email,
+        task.newsletter_issue_id,
+        subscriber_id,
+        SubscriberStatus::Ok.to_string()
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(tasks)
+}
+
+/// The issues, oldest first.
+#[tracing::instrument(skip_all)]
+async fn get_issues(
+    pool: &PgPool,
+    issue_ids: &[Uuid],
+) -> Result<Vec<NewsletterIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = ANY($1)
+        ORDER BY published_at
+        "#,
+        issue_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{digest, retry_delay, NewsletterIssue, BASE_RETRY_DELAY, MAX_RETRY_DELAY};
+    use crate::domain::DeliveryFrequency;
+
+    #[test]
+    fn first_retry_is_scheduled_within_the_base_delay() {
+        let delay = retry_delay(0);
+        assert!(delay >= BASE_RETRY_DELAY / 2);
+        assert!(delay <= BASE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially() {
+        let delay = retry_delay(3);
+        assert!(delay >= BASE_RETRY_DELAY * 4);
+        assert!(delay <= BASE_RETRY_DELAY * 8);
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        for n_retries in [10, 100, i16::MAX] {
+            assert!(retry_delay(n_retries) <= MAX_RETRY_DELAY);
+        }
+    }
+
+    #[test]
+    fn a_digest_holds_every_issue_under_its_title() {
+        let issue = |title: &str, content: &str| NewsletterIssue {
+            title: title.into(),
+            text_content: content.into(),
+            html_content: format!("<p>{content}</p>"),
+        };
+        let issues = [issue("Q&A", "First"), issue("Release notes", "Second")];
+
+        let digest = digest(DeliveryFrequency::Weekly, &issues);
+
+        assert_eq!(digest.title, "Your weekly digest: 2 new issues");
+        assert_eq!(
+            digest.html_content,
+            "<h2>Q&amp;A</h2>\n<p>First</p>\n<hr>\n<h2>Release notes</h2>\n<p>Second</p>"
+        );
+        assert_eq!(
+            digest.text_content,
+            "Q&A\n\nFirst\n\n---\n\nRelease notes\n\nSecond"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use serde::Serialize;
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{ApplicationBaseUrl, HmacSecret},
+    routes::{preferences_link, unsubscribe_link},
+};
+
+/// Wraps the content of a newsletter issue into the email templates, adding
+/// the footer with the subscriber's own unsubscribe and preferences links.
+pub struct IssueRenderer {
+    templates: Tera,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+}
+
+pub struct RenderedIssue {
+    pub html_content: String,
+    pub text_content: String,
+    pub unsubscribe_link: String,
+    list_unsubscribe: String,
+}
+
+#[derive(Serialize)]
+struct NewsletterContext<'a> {
+    content: &'a str,
+    unsubscribe_link: &'a str,
+    preferences_link: &'a str,
+}
+
+impl RenderedIssue {
+    /// `List-Unsubscribe` headers, including the RFC 8058 one-click variant
+    /// required by bulk-sender rules.
+    pub fn headers(&self) -> [(&str, &str); 2] {
+        [
+            ("List-Unsubscribe", &self.list_unsubscribe),
+            ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+        ]
+    }
+}
+
+impl IssueRenderer {
+    pub fn new(templates: Tera, base_url: ApplicationBaseUrl, hmac_secret: HmacSecret) -> Self {
+        Self {
+            templates,
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn render(
+        &self,
+        html_content: &str,
+        text_content: &str,
+        subscriber_id: &Uuid,
+    ) -> Result<RenderedIssue, tera::Error> {
+        let unsubscribe_link = unsubscribe_link(&self.base_url.0, &self.hmac_secret, subscriber_id);
+        let preferences_link = preferences_link(&self.base_url.0, &self.hmac_secret, subscriber_id);
+
+        let render = |template: &str, content: &str| {
+            let context = NewsletterContext {
+                content,
+                unsubscribe_link: &unsubscribe_link,
+                preferences_link: &preferences_link,
+            };
+            self.templates
+                .render(template, &TeraContext::from_serialize(&context)?)
+        };
+        let html_content = render("newsletter.html", html_content)?;
+        let text_content = render("newsletter.txt", text_content)?;
+
+        Ok(RenderedIssue {
+            html_content,
+            text_content,
+            list_unsubscribe: format!("<{unsubscribe_link}>"),
+            unsubscribe_link,
+        })
+    }
+}
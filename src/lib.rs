@@ -1,15 +1,45 @@
-use crate::configuration::ApplicationBaseUrl;
+use crate::authentication::reject_anonymous_users;
+use crate::clock::Clock;
+use crate::configuration::{ApplicationSettings, HmacSecret};
+use crate::csrf::csrf_protection;
 use crate::email_client::EmailClient;
-use actix_web::{dev::Server, web, App, HttpServer};
+use crate::issue_renderer::IssueRenderer;
+use crate::metrics::track_requests;
+use crate::rate_limit::{rate_limit_by_ip, RateLimiter};
+use crate::session::PgSessionStore;
+use actix_session::SessionMiddleware;
+use actix_web::{cookie::Key, dev::Server, middleware::from_fn, web, App, HttpServer};
+use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use secrecy::ExposeSecret;
 use sqlx::PgPool;
 use tera::Tera;
 use tracing_actix_web::TracingLogger;
 
+pub mod audit;
+pub mod authentication;
+pub mod cli;
+pub mod clock;
 pub mod configuration;
+pub mod confirmation_email_worker;
+pub mod csrf;
 pub mod domain;
+pub mod drafts;
 pub mod email_client;
+pub mod erasure;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_renderer;
+pub mod lists;
+pub mod metrics;
+pub mod migrations;
+pub mod newsletter_scheduler;
+pub mod pending_subscriber_cleanup;
+pub mod rate_limit;
 pub mod routes;
+pub mod session;
 pub mod startup;
+pub mod subscriber_export;
+pub mod subscriber_import;
 pub mod telemetry;
 pub mod utils;
 
@@ -17,26 +47,179 @@ pub fn run(
     listener: std::net::TcpListener,
     database: PgPool,
     email_client: EmailClient,
-    base_url: ApplicationBaseUrl,
+    settings: ApplicationSettings,
     templates: Tera,
+    clock: Clock,
 ) -> Result<Server, std::io::Error> {
+    let hmac_secret = HmacSecret(settings.hmac_secret.clone());
+    // Session and flash message cookies are signed with a key derived from
+    // the HMAC secret rather than with the secret itself.
+    let secret_key = Key::derive_from(hmac_secret.0.expose_secret().as_bytes());
+    let message_framework =
+        FlashMessagesFramework::builder(CookieMessageStore::builder(secret_key.clone()).build())
+            .build();
+    let session_store = PgSessionStore::new(database.clone());
     let database = web::Data::new(database);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(base_url);
+    let base_url = web::Data::new(settings.base_url.clone());
+    // Draft previews and test sends go through the same templates as the real thing
+    let renderer = web::Data::new(IssueRenderer::new(
+        templates.clone(),
+        settings.base_url.clone(),
+        hmac_secret.clone(),
+    ));
+    let hmac_secret = web::Data::new(hmac_secret);
+    let suppression_salt = web::Data::new(settings.suppression_salt());
+    let subscription_token_ttl = web::Data::new(settings.subscription_token_ttl());
+    let confirmation_resend_cooldown =
+        web::Data::new(settings.rate_limit.confirmation_resend_cooldown());
+    let readiness_settings = web::Data::new(settings.readiness.clone());
+    let test_recipients = web::Data::new(settings.test_recipients());
+    // Shared by all workers, otherwise each of them would have its own buckets
+    let rate_limiter = web::Data::new(RateLimiter::new(&settings.rate_limit));
     let tera = web::Data::new(templates);
+    let clock = web::Data::new(clock);
+    let grace_period = settings.shutdown_grace_period();
+    // Otherwise served by `run_metrics_server`, on a port of its own
+    let serve_metrics = settings.metrics.port.is_none();
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(csrf_protection))
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(from_fn(track_requests))
             .wrap(TracingLogger::default())
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(routes::metrics));
+                }
+            })
             .route("/healthz", web::get().to(routes::ping))
-            .route("/subscribe", web::post().to(routes::subscribe))
+            .route("/livez", web::get().to(routes::ping))
+            .route("/readyz", web::get().to(routes::readiness))
+            .route("/subscribe", web::get().to(routes::subscribe_form))
+            .route(
+                "/subscribe",
+                web::post()
+                    .to(routes::subscribe)
+                    .wrap(from_fn(rate_limit_by_ip)),
+            )
             .route("/subscribe/confirm", web::get().to(routes::confirm))
             .route("/newsletters", web::post().to(routes::publish_newsletter))
+            .route(
+                "/newsletters/{newsletter_issue_id}",
+                web::delete().to(routes::cancel_newsletter),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/schedule",
+                web::put().to(routes::reschedule_newsletter),
+            )
+            .route("/preferences", web::get().to(routes::preferences_form))
+            .route("/preferences", web::post().to(routes::update_preferences))
+            .route(
+                "/preferences/confirm-email",
+                web::get().to(routes::confirm_email_change),
+            )
+            .route("/unsubscribe", web::get().to(routes::unsubscribe_form))
+            .route("/unsubscribe", web::post().to(routes::unsubscribe))
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route(
+                        "/newsletters",
+                        web::get().to(routes::publish_newsletter_form),
+                    )
+                    .route(
+                        "/newsletters",
+                        web::post().to(routes::publish_newsletter_from_form),
+                    )
+                    .route("/drafts", web::get().to(routes::drafts_page))
+                    .route("/drafts", web::post().to(routes::create_draft_from_form))
+                    .route("/drafts/{draft_id}", web::get().to(routes::draft_page))
+                    .route("/drafts/{draft_id}", web::post().to(routes::save_draft))
+                    .route(
+                        "/drafts/{draft_id}/delete",
+                        web::post().to(routes::delete_draft_from_form),
+                    )
+                    .route(
+                        "/drafts/{draft_id}/preview",
+                        web::get().to(routes::preview_draft),
+                    )
+                    .route(
+                        "/drafts/{draft_id}/test",
+                        web::post().to(routes::send_test_email),
+                    )
+                    .route("/lists", web::get().to(routes::lists_page))
+                    .route("/lists", web::post().to(routes::create_list_from_form))
+                    .route(
+                        "/subscribers/export",
+                        web::get().to(routes::export_subscribers),
+                    )
+                    .route(
+                        "/subscribers/data",
+                        web::get().to(routes::export_subscriber_data),
+                    )
+                    .route(
+                        "/subscribers/erase",
+                        web::post().to(routes::erase_subscriber_from_form),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::get().to(routes::import_subscribers_form),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::post().to(routes::upload_subscribers),
+                    )
+                    .route("/password", web::get().to(routes::change_password_form))
+                    .route("/password", web::post().to(routes::change_password))
+                    .route("/logout", web::post().to(routes::log_out)),
+            )
             .app_data(database.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(suppression_salt.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(confirmation_resend_cooldown.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(readiness_settings.clone())
+            .app_data(test_recipients.clone())
             .app_data(tera.clone())
+            .app_data(renderer.clone())
+            .app_data(clock.clone())
     })
+    // Signals are handled by `Application`, which also stops the background tasks
+    .disable_signals()
+    .shutdown_timeout(grace_period.as_secs())
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
+/// Serves `/metrics` alone, for deployments that keep it off the public port.
+pub fn run_metrics_server(
+    listener: std::net::TcpListener,
+    database: PgPool,
+    grace_period: std::time::Duration,
+) -> Result<Server, std::io::Error> {
+    let database = web::Data::new(database);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(routes::metrics))
+            .app_data(database.clone())
+    })
+    .disable_signals()
+    .shutdown_timeout(grace_period.as_secs())
     .listen(listener)?
     .run();
 
@@ -0,0 +1,191 @@
+use anyhow::Context;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    domain::{ListSlug, SubscriberStatus},
+    utils::error_chain_fmt,
+};
+
+/// The list subscribers join and issues go to when no list is given. Every
+/// subscriber from before there were several lists is a member of it.
+pub const DEFAULT_LIST: &str = "newsletter";
+
+pub fn default_list() -> ListSlug {
+    ListSlug::parse(DEFAULT_LIST.to_string()).expect("The default list slug is valid")
+}
+
+#[derive(Debug, Serialize)]
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    /// Members who confirmed and are still subscribed.
+    pub n_subscribers: i64,
+}
+
+#[derive(thiserror::Error)]
+pub enum ListError {
+    #[error("{0}")]
+    InvalidSlug(String),
+
+    #[error("There is no list called {0}.")]
+    UnknownList(ListSlug),
+
+    #[error("There is already a list called {0}.")]
+    SlugTaken(ListSlug),
+
+    #[error("The list name cannot be empty.")]
+    EmptyName,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Fetching the lists", skip(pool))]
+pub async fn all_lists(pool: &PgPool) -> Result<Vec<List>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        List,
+        r#"
+        SELECT
+            lists.list_id,
+            lists.slug,
+            lists.name,
+            count(subscriptions.id) AS "n_subscribers!"
+        FROM lists
+        LEFT JOIN list_memberships
+            ON list_memberships.list_id = lists.list_id AND list_memberships.status = $1
+        LEFT JOIN subscriptions
+            ON subscriptions.id = list_memberships.subscriber_id AND subscriptions.status = $1
+        GROUP BY lists.list_id
+        ORDER BY lists.name
+        "#,
+        SubscriberStatus::Ok.to_string()
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the lists")?;
+
+    Ok(lists)
+}
+
+#[tracing::instrument(name = "Creating a list", skip(pool))]
+pub async fn create_list(pool: &PgPool, slug: &ListSlug, name: &str) -> Result<Uuid, ListError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ListError::EmptyName);
+    }
+
+    let list_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list_id,
+        slug.as_ref(),
+        name,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert the list")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(ListError::SlugTaken(slug.clone()));
+    }
+
+    Ok(list_id)
+}
+
+/// Ids of the lists, in the same order. Fails on the first list that does not exist.
+#[tracing::instrument(name = "Looking up lists", skip(executor))]
+pub async fn find_list_ids(
+    executor: impl PgExecutor<'_>,
+    slugs: &[ListSlug],
+) -> Result<Vec<Uuid>, ListError> {
+    let raw_slugs: Vec<String> = slugs.iter().map(|slug| slug.to_string()).collect();
+    let lists = sqlx::query!(
+        "SELECT list_id, slug FROM lists WHERE slug = ANY($1)",
+        &raw_slugs[..]
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to look up the lists")?;
+
+    slugs
+        .iter()
+        .map(|slug| {
+            lists
+                .iter()
+                .find(|list| list.slug == slug.as_ref())
+                .map(|list| list.list_id)
+                .ok_or_else(|| ListError::UnknownList(slug.clone()))
+        })
+        .collect()
+}
+
+/// Ids of the lists named in a request, or of the default list if none is.
+pub async fn requested_list_ids(
+    executor: impl PgExecutor<'_>,
+    mut slugs: Vec<String>,
+) -> Result<Vec<Uuid>, ListError> {
+    slugs.sort();
+    slugs.dedup();
+    let mut slugs = slugs
+        .into_iter()
+        .map(ListSlug::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ListError::InvalidSlug)?;
+    if slugs.is_empty() {
+        slugs.push(default_list());
+    }
+
+    find_list_ids(executor, &slugs).await
+}
+
+pub async fn find_list_id(
+    executor: impl PgExecutor<'_>,
+    slug: &ListSlug,
+) -> Result<Uuid, ListError> {
+    let ids = find_list_ids(executor, std::slice::from_ref(slug)).await?;
+    Ok(ids[0])
+}
+
+/// Adds the subscriber to the list, or brings them back to it with `status`
+/// if they left. A confirmed membership is left as it is.
+#[tracing::instrument(name = "Adding a list membership", skip(executor))]
+pub async fn add_membership(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    status: SubscriberStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = EXCLUDED.status
+        WHERE list_memberships.status <> $5
+        "#,
+        list_id,
+        subscriber_id,
+        status.to_string(),
+        Utc::now(),
+        SubscriberStatus::Ok.to_string()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
@@ -1,15 +1,35 @@
+use clap::Parser;
+use tracing_subscriber::fmt::MakeWriter;
+use zero2prod::cli::{self, Cli, Command};
 use zero2prod::configuration::Settings;
-use zero2prod::startup::Application;
 use zero2prod::telemetry;
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = telemetry::get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    telemetry::init_subscriber(subscriber);
-
+async fn main() -> Result<(), anyhow::Error> {
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
     let config = Settings::get().expect("Failed to read configuration.");
-    let app = Application::build(config).await?;
-    app.server.await?;
 
-    Ok(())
+    // One-off commands log to stderr, their output goes to stdout
+    match command {
+        Command::Serve => init_telemetry(&config, std::io::stdout),
+        _ => init_telemetry(&config, std::io::stderr),
+    }
+
+    let outcome = cli::run(command, config, &mut std::io::stdout()).await;
+
+    // Exports the spans still waiting in the batch
+    opentelemetry::global::shutdown_tracer_provider();
+
+    outcome
+}
+
+fn init_telemetry<Sink>(config: &Settings, sink: Sink)
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let tracer = telemetry::get_tracer_provider("zero2prod".into(), &config.telemetry)
+        .expect("Failed to set up the OTLP exporter.")
+        .map(telemetry::init_tracer_provider);
+    let subscriber = telemetry::get_subscriber("zero2prod".into(), "info".into(), sink, tracer);
+    telemetry::init_subscriber(subscriber);
 }
@@ -0,0 +1,172 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use sqlx::PgPool;
+
+/// Requests that did not match any route share a label, so that scanners
+/// can't blow up the number of series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests handled, by route pattern and status code.",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time spent handling HTTP requests, by route pattern.",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "db_pool_connections",
+        "Connections currently opened by the database pool, by state.",
+        &["state"]
+    )
+    .unwrap()
+});
+
+static EMAILS_SENT_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "emails_sent_total",
+        "Emails accepted by the email transport."
+    )
+    .unwrap()
+});
+
+static EMAILS_FAILED_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "emails_failed_total",
+        "Emails the email transport failed to send."
+    )
+    .unwrap()
+});
+
+static SUBSCRIPTION_FUNNEL_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "subscription_funnel_total",
+        "Subscribers reaching each step of the subscription funnel.",
+        &["step"]
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug)]
+pub enum FunnelStep {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl FunnelStep {
+    const ALL: [FunnelStep; 3] = [Self::Subscribed, Self::Confirmed, Self::Unsubscribed];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Subscribed => "subscribed",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+pub fn record_funnel_step(step: FunnelStep) {
+    SUBSCRIPTION_FUNNEL_TOTAL
+        .with_label_values(&[step.as_str()])
+        .inc();
+}
+
+pub fn record_email_outcome<T, E>(outcome: &Result<T, E>) {
+    match outcome {
+        Ok(_) => EMAILS_SENT_TOTAL.inc(),
+        Err(_) => EMAILS_FAILED_TOTAL.inc(),
+    }
+}
+
+/// Counts and times every request, labelled by the pattern of the route that
+/// handled it (e.g. `/subscribe/confirm`) rather than by its raw path.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let outcome = next.call(req).await;
+
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+
+    outcome
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render(pool: &PgPool) -> Result<String, anyhow::Error> {
+    // Gauges are sampled on scrape rather than kept up to date
+    let total = i64::from(pool.size());
+    let idle = i64::try_from(pool.num_idle())?;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["active"])
+        .set(total - idle);
+
+    // Series show up from the start instead of after their first increment
+    Lazy::force(&HTTP_REQUESTS_TOTAL);
+    Lazy::force(&HTTP_REQUEST_DURATION_SECONDS);
+    Lazy::force(&EMAILS_SENT_TOTAL);
+    Lazy::force(&EMAILS_FAILED_TOTAL);
+    for step in FunnelStep::ALL {
+        SUBSCRIPTION_FUNNEL_TOTAL.with_label_values(&[step.as_str()]);
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record_funnel_step, FunnelStep, SUBSCRIPTION_FUNNEL_TOTAL};
+
+    #[test]
+    fn recording_a_funnel_step_increments_its_counter() {
+        let before = SUBSCRIPTION_FUNNEL_TOTAL
+            .with_label_values(&["unsubscribed"])
+            .get();
+
+        record_funnel_step(FunnelStep::Unsubscribed);
+
+        let after = SUBSCRIPTION_FUNNEL_TOTAL
+            .with_label_values(&["unsubscribed"])
+            .get();
+        assert_eq!(after, before + 1);
+    }
+}
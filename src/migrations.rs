@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use sqlx::{
+    migrate::{Migration, Migrator},
+    PgPool,
+};
+
+/// The migrations under `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the file changed since: `up` refuses to run until it is restored.
+    Modified,
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// Applies the pending migrations.
+///
+/// The migrator holds a Postgres advisory lock while it runs, so replicas
+/// starting together apply each migration once and wait for each other.
+#[tracing::instrument(name = "Running database migrations", skip(pool), err)]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    MIGRATOR
+        .run(pool)
+        .await
+        .context("Failed to apply the database migrations")?;
+
+    Ok(())
+}
+
+/// The state of every embedded migration against the database.
+pub async fn migration_status(pool: &PgPool) -> Result<Vec<MigrationStatus>, anyhow::Error> {
+    let applied = applied_checksums(pool).await?;
+
+    Ok(up_migrations()
+        .map(|migration| {
+            let state = match applied.get(&migration.version) {
+                None => MigrationState::Pending,
+                Some(checksum) if checksum.as_slice() != &*migration.checksum => {
+                    MigrationState::Modified
+                }
+                Some(_) => MigrationState::Applied,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect())
+}
+
+/// Versions of the embedded migrations that are not applied yet.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<i64>, anyhow::Error> {
+    let applied = applied_checksums(pool).await?;
+
+    Ok(up_migrations()
+        .filter(|migration| !applied.contains_key(&migration.version))
+        .map(|migration| migration.version)
+        .collect())
+}
+
+fn up_migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+}
+
+async fn applied_checksums(pool: &PgPool) -> Result<HashMap<i64, Vec<u8>>, anyhow::Error> {
+    // The table only exists once the first migration has been applied
+    let has_migrations_table: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await
+            .context("Failed to look for the migrations table")?;
+    if !has_migrations_table {
+        return Ok(HashMap::new());
+    }
+
+    let applied = sqlx::query!("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+        .fetch_all(pool)
+        .await
+        .context("Failed to read the applied migrations")?
+        .into_iter()
+        .map(|row| (row.version, row.checksum))
+        .collect();
+
+    Ok(applied)
+}
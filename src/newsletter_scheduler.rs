@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    clock::Clock, configuration::Settings, routes::publish_issue, startup::get_connection_pool,
+};
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+pub async fn run_scheduler_until_stopped(
+    config: Settings,
+    clock: Clock,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&config.database);
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+        // A failed run is retried on the next tick, the task itself must keep going
+        if let Err(e) = publish_due_issues(&connection_pool, clock.now()).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to look for due newsletter issues"
+            );
+        }
+    }
+    connection_pool.close().await;
+
+    Ok(())
+}
+
+/// Publishes the scheduled issues whose send time has come by `now`, each in
+/// a transaction of its own: an issue that fails to publish is logged and
+/// retried on the next run, without holding back the others. Issues locked by
+/// another replica, or by an admin rescheduling them, are left for the next run.
+#[tracing::instrument(name = "Publishing due newsletter issues", skip(pool))]
+pub async fn publish_due_issues(pool: &PgPool, now: DateTime<Utc>) -> Result<u64, anyhow::Error> {
+    let due_issues = sqlx::query_scalar!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE published_at IS NULL AND scheduled_at <= $1
+        "#,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut n_published = 0;
+    for newsletter_issue_id in due_issues {
+        match publish_due_issue(pool, newsletter_issue_id, now).await {
+            Ok(true) => n_published += 1,
+            Ok(false) => {}
+            Err(e) => tracing::error!(
+                %newsletter_issue_id,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to publish a scheduled newsletter issue"
+            ),
+        }
+    }
+    if n_published > 0 {
+        tracing::info!(n_published, "Published due newsletter issues");
+    }
+
+    Ok(n_published)
+}
+
+/// Returns whether the issue was published, rather than left alone because
+/// it is locked or no longer due.
+async fn publish_due_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let is_due = sqlx::query_scalar!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL AND scheduled_at <= $2
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        newsletter_issue_id,
+        now
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .is_some();
+    if !is_due {
+        return Ok(false);
+    }
+
+    publish_issue(&mut transaction, newsletter_issue_id, now).await?;
+    transaction.commit().await?;
+
+    Ok(true)
+}
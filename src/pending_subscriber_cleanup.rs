@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    configuration::Settings, domain::SubscriberStatus, session::purge_expired_sessions,
+    startup::get_connection_pool,
+};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub async fn run_cleanup_until_stopped(
+    config: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&config.database);
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+        // A failed run is retried on the next tick, the task itself must keep going
+        let _ = purge_expired_pending_subscribers(&connection_pool).await;
+        let _ = purge_expired_sessions(&connection_pool).await;
+    }
+    connection_pool.close().await;
+
+    Ok(())
+}
+
+/// Deletes subscribers who never confirmed their subscription and no longer
+/// hold a valid confirmation token, together with their tokens.
+#[tracing::instrument(name = "Purging expired pending subscribers", skip(pool), err)]
+pub async fn purge_expired_pending_subscribers(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        WITH expired AS (
+            SELECT id
+            FROM subscriptions
+            WHERE
+                status = $1 AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM subscription_tokens
+                    WHERE
+                        subscriber_id = subscriptions.id AND
+                        expires_at > now()
+                )
+        ), deleted_tokens AS (
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id IN (SELECT id FROM expired)
+        )
+        DELETE FROM subscriptions
+        WHERE id IN (SELECT id FROM expired)
+        "#,
+        SubscriberStatus::PendingConfirmation.to_string()
+    )
+    .execute(pool)
+    .await?;
+
+    let n_purged = result.rows_affected();
+    tracing::info!(n_purged, "Purged expired pending subscribers");
+
+    Ok(n_purged)
+}
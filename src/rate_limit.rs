@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, HttpResponse, ResponseError,
+};
+use reqwest::StatusCode;
+
+use crate::configuration::RateLimitSettings;
+
+/// Above this many tracked clients, buckets that refilled completely are
+/// dropped: they behave exactly like a client we have never seen.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(thiserror::Error, Debug)]
+#[error("Too many requests, retry in {} seconds.", retry_after_seconds(.retry_after))]
+pub struct TooManyRequests {
+    pub retry_after: Duration,
+}
+
+fn retry_after_seconds(retry_after: &Duration) -> u64 {
+    // Rounded up, retrying a bit too early would be rejected again
+    retry_after
+        .as_secs()
+        .saturating_add(u64::from(retry_after.subsec_nanos() > 0))
+}
+
+impl ResponseError for TooManyRequests {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header((
+                header::RETRY_AFTER,
+                retry_after_seconds(&self.retry_after).to_string(),
+            ))
+            .body(self.to_string())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// In-process token bucket limiter keyed by client IP.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    trusted_proxies: Vec<IpAddr>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            capacity: f64::from(settings.subscribe_burst),
+            refill_per_second: f64::from(settings.subscribe_per_minute) / 60.0,
+            trusted_proxies: settings.trusted_proxies.clone(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), TooManyRequests> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.capacity);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let retry_after = if self.refill_per_second > 0.0 {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second)
+        } else {
+            Duration::MAX
+        };
+        Err(TooManyRequests { retry_after })
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity)
+    }
+
+    /// The address of the client, as seen by the first proxy we don't trust.
+    ///
+    /// `X-Forwarded-For` is only looked at when the request comes from a
+    /// trusted proxy, and is read from the right since anything on its left
+    /// may have been made up by the client.
+    fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        if !self.trusted_proxies.contains(&peer) {
+            return peer;
+        }
+
+        let mut client = peer;
+        for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.trusted_proxies.contains(&hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Throttles requests per client IP, using the [`RateLimiter`] registered as
+/// application data.
+pub async fn rate_limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered")
+        .clone();
+
+    if let Some(peer) = req.peer_addr() {
+        let forwarded_for = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok());
+        let client = limiter.client_ip(peer.ip(), forwarded_for);
+
+        if let Err(e) = limiter.acquire(client, Instant::now()) {
+            tracing::warn!(%client, "Rate limit exceeded");
+            return Err(e.into());
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use claims::{assert_err, assert_ok};
+
+    use super::RateLimiter;
+    use crate::configuration::RateLimitSettings;
+
+    fn limiter(burst: u32, per_minute: u32, trusted_proxies: &[&str]) -> RateLimiter {
+        RateLimiter::new(&RateLimitSettings {
+            subscribe_burst: burst,
+            subscribe_per_minute: per_minute,
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|ip| ip.parse().unwrap())
+                .collect(),
+            confirmation_resend_cooldown_seconds: 0,
+        })
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn requests_beyond_the_burst_are_rejected() {
+        let limiter = limiter(2, 60, &[]);
+        let now = Instant::now();
+
+        assert_ok!(limiter.acquire(ip("10.0.0.1"), now));
+        assert_ok!(limiter.acquire(ip("10.0.0.1"), now));
+        let e = assert_err!(limiter.acquire(ip("10.0.0.1"), now));
+        assert_eq!(e.retry_after, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn clients_have_their_own_bucket() {
+        let limiter = limiter(1, 60, &[]);
+        let now = Instant::now();
+
+        assert_ok!(limiter.acquire(ip("10.0.0.1"), now));
+        assert_ok!(limiter.acquire(ip("10.0.0.2"), now));
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = limiter(1, 60, &[]);
+        let now = Instant::now();
+
+        assert_ok!(limiter.acquire(ip("10.0.0.1"), now));
+        assert_err!(limiter.acquire(ip("10.0.0.1"), now + Duration::from_millis(500)));
+        assert_ok!(limiter.acquire(ip("10.0.0.1"), now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let limiter = limiter(1, 60, &[]);
+
+        let client = limiter.client_ip(ip("10.0.0.1"), Some("1.2.3.4"));
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right_behind_trusted_proxies() {
+        let limiter = limiter(1, 60, &["10.0.0.1", "10.0.0.2"]);
+
+        let client = limiter.client_ip(ip("10.0.0.1"), Some("6.6.6.6, 1.2.3.4, 10.0.0.2"));
+        assert_eq!(client, ip("1.2.3.4"));
+    }
+
+    #[test]
+    fn a_malformed_forwarded_for_falls_back_to_the_last_valid_hop() {
+        let limiter = limiter(1, 60, &["10.0.0.1"]);
+
+        assert_eq!(
+            limiter.client_ip(ip("10.0.0.1"), Some("not-an-ip")),
+            ip("10.0.0.1")
+        );
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), None), ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{
+    authentication::UserId,
+    csrf::CsrfToken,
+    utils::{e500, render_page},
+};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Show the admin dashboard", skip_all, fields(user_id = %*user_id))]
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = get_username(**user_id, &pool).await.map_err(e500)?;
+
+    let mut context = TeraContext::new();
+    context.insert("username", &username);
+
+    render_page(
+        &template,
+        "admin/dashboard.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!("SELECT username FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username")?;
+
+    Ok(row.username)
+}
@@ -0,0 +1,261 @@
+use crate::{
+    configuration::TestRecipients,
+    csrf::CsrfToken,
+    domain::Email,
+    drafts::{all_drafts, create_draft, delete_draft, get_draft, update_draft, Draft},
+    email_client::EmailClient,
+    issue_renderer::IssueRenderer,
+    lists::{all_lists, default_list},
+    utils::{e400, e500, render_page, see_other},
+};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Show the drafts", skip_all)]
+pub async fn drafts_page(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = TeraContext::new();
+    context.insert("drafts", &all_drafts(&pool).await.map_err(e500)?);
+
+    render_page(
+        &template,
+        "admin/drafts.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Create a draft", skip_all)]
+pub async fn create_draft_from_form(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let DraftFormData {
+        title,
+        text_content,
+        html_content,
+    } = form.into_inner();
+    let draft_id = create_draft(&pool, &title, &text_content, &html_content)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+
+    Ok(see_other(&draft_location(draft_id)))
+}
+
+/// The draft's editor, along with the forms to preview it, send it to
+/// yourself and publish it.
+#[tracing::instrument(name = "Show a draft", skip_all, fields(%draft_id))]
+pub async fn draft_page(
+    draft_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+    template: web::Data<Tera>,
+    test_recipients: web::Data<TestRecipients>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(draft) = get_draft(&pool, *draft_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let mut context = TeraContext::new();
+    context.insert("draft", &draft);
+    // Publishing goes through the newsletter form handler, hence its key
+    context.insert("idempotency_key", &Uuid::new_v4().to_string());
+    context.insert("lists", &all_lists(&pool).await.map_err(e500)?);
+    context.insert("default_list", &default_list().to_string());
+    context.insert("test_recipients", &test_recipients.0);
+
+    render_page(
+        &template,
+        "admin/draft.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[tracing::instrument(name = "Save a draft", skip_all, fields(%draft_id))]
+pub async fn save_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let DraftFormData {
+        title,
+        text_content,
+        html_content,
+    } = form.into_inner();
+    if !update_draft(&pool, draft_id, &title, &text_content, &html_content)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The draft has been saved.").send();
+
+    Ok(see_other(&draft_location(draft_id)))
+}
+
+#[tracing::instrument(name = "Delete a draft", skip_all, fields(%draft_id))]
+pub async fn delete_draft_from_form(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    delete_draft(pool.get_ref(), *draft_id)
+        .await
+        .context("Failed to delete the draft")
+        .map_err(e500)?;
+    FlashMessage::info("The draft has been deleted.").send();
+
+    Ok(see_other("/admin/drafts"))
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewParameters {
+    /// The subscriber the issue is rendered for, whose links end up in the footer.
+    email: String,
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+/// The email exactly as the given subscriber would get it, templates and
+/// footer included.
+#[tracing::instrument(name = "Preview a draft", skip_all, fields(%draft_id, format = ?params.format))]
+pub async fn preview_draft(
+    draft_id: web::Path<Uuid>,
+    params: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+    renderer: web::Data<IssueRenderer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let PreviewParameters { email, format } = params.into_inner();
+    let email = Email::parse(email).map_err(e400)?;
+    let Some(draft) = get_draft(&pool, *draft_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let subscriber_id = sqlx::query_scalar!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1)",
+        email.as_ref()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the sample subscriber")
+    .map_err(e500)?
+    .ok_or_else(|| e400(format!("{email} is not a subscriber.")))?;
+
+    let rendered = renderer
+        .render(&draft.html_content, &draft.text_content, &subscriber_id)
+        .map_err(e500)?;
+    let response = match format {
+        // The draft's HTML is served from our own origin: sandboxed, any
+        // script in it can't act with the admin's session
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .insert_header(("Content-Security-Policy", "sandbox"))
+            .body(rendered.html_content),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(rendered.text_content),
+    };
+
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct TestEmailFormData {
+    email: String,
+}
+
+/// Sends the draft to one of the configured test recipients, straight away
+/// rather than through the delivery queue. The footer links are made for no
+/// subscriber in particular.
+#[tracing::instrument(name = "Send a test email", skip_all, fields(%draft_id))]
+pub async fn send_test_email(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<TestEmailFormData>,
+    pool: web::Data<PgPool>,
+    renderer: web::Data<IssueRenderer>,
+    email_client: web::Data<EmailClient>,
+    test_recipients: web::Data<TestRecipients>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let Some(draft) = get_draft(&pool, draft_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let recipient = Email::parse(form.into_inner().email).and_then(|recipient| {
+        if test_recipients.allows(&recipient) {
+            Ok(recipient)
+        } else {
+            Err(format!("{recipient} is not one of the test recipients."))
+        }
+    });
+    let recipient = match recipient {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_location(draft_id)));
+        }
+    };
+
+    send_draft(&email_client, &renderer, &draft, &recipient)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!("A test email has been sent to {recipient}.")).send();
+
+    Ok(see_other(&draft_location(draft_id)))
+}
+
+async fn send_draft(
+    email_client: &EmailClient,
+    renderer: &IssueRenderer,
+    draft: &Draft,
+    recipient: &Email,
+) -> Result<(), anyhow::Error> {
+    let rendered = renderer
+        .render(&draft.html_content, &draft.text_content, &Uuid::nil())
+        .context("Failed to render the draft")?;
+    email_client
+        .send_email_with_headers(
+            recipient,
+            &format!("[Test] {}", draft.title),
+            &rendered.html_content,
+            &rendered.text_content,
+            &rendered.headers(),
+        )
+        .await
+        .context("Failed to send the test email")?;
+
+    Ok(())
+}
+
+fn draft_location(draft_id: Uuid) -> String {
+    format!("/admin/drafts/{draft_id}")
+}
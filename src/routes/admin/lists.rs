@@ -0,0 +1,56 @@
+use crate::{
+    csrf::CsrfToken,
+    domain::ListSlug,
+    lists::{all_lists, create_list, ListError},
+    utils::{e500, render_page, see_other},
+};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+
+#[tracing::instrument(name = "Show the mailing lists", skip_all)]
+pub async fn lists_page(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = TeraContext::new();
+    context.insert("lists", &all_lists(&pool).await.map_err(e500)?);
+
+    render_page(
+        &template,
+        "admin/lists.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct NewListFormData {
+    slug: String,
+    name: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip_all, fields(slug = %form.slug))]
+pub async fn create_list_from_form(
+    form: web::Form<NewListFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let NewListFormData { slug, name } = form.into_inner();
+    let created = match ListSlug::parse(slug) {
+        Ok(slug) => create_list(&pool, &slug, &name).await.map(|_| slug),
+        Err(e) => Err(ListError::InvalidSlug(e)),
+    };
+    match created {
+        Ok(slug) => FlashMessage::info(format!("The list {slug} has been created.")).send(),
+        Err(ListError::UnexpectedError(e)) => return Err(e500(e)),
+        Err(e) => FlashMessage::error(e.to_string()).send(),
+    }
+
+    Ok(see_other("/admin/lists"))
+}
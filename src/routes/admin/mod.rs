@@ -0,0 +1,15 @@
+mod dashboard;
+mod drafts;
+mod lists;
+mod logout;
+mod newsletters;
+mod password;
+mod subscribers;
+
+pub use dashboard::*;
+pub use drafts::*;
+pub use lists::*;
+pub use logout::*;
+pub use newsletters::*;
+pub use password::*;
+pub use subscribers::*;
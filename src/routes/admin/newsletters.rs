@@ -0,0 +1,130 @@
+use crate::{
+    authentication::UserId,
+    clock::Clock,
+    csrf::CsrfToken,
+    drafts::delete_draft,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    lists::{all_lists, default_list, requested_list_ids, ListError},
+    routes::{insert_newsletter_issue, publish_issue},
+    utils::{e400, e500, render_page, see_other},
+};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Show the newsletter form", skip_all)]
+pub async fn publish_newsletter_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // A fresh key per rendered form: submitting the same form twice (double
+    // click, browser retry) publishes the issue only once.
+    let mut context = TeraContext::new();
+    context.insert("idempotency_key", &Uuid::new_v4().to_string());
+    context.insert("lists", &all_lists(&pool).await.map_err(e500)?);
+    context.insert("default_list", &default_list().to_string());
+
+    render_page(
+        &template,
+        "admin/newsletters.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct NewsletterFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+    /// One checkbox per list, hence a field repeated in the form.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Set when publishing from a draft, which goes away along with it.
+    draft_id: Option<Uuid>,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin dashboard",
+    skip_all,
+    fields(user_id = %*user_id)
+)]
+pub async fn publish_newsletter_from_form(
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let NewsletterFormData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+        lists,
+        draft_id,
+    } = serde_html_form::from_bytes(&body).map_err(e400)?;
+    let idempotency_key = IdempotencyKey::parse(idempotency_key).map_err(e400)?;
+    let list_ids = match requested_list_ids(pool.get_ref(), lists).await {
+        Ok(list_ids) => list_ids,
+        Err(ListError::UnexpectedError(e)) => return Err(e500(e)),
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => *transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        &list_ids,
+        None,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
+    publish_issue(&mut transaction, issue_id, clock.now())
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+    if let Some(draft_id) = draft_id {
+        delete_draft(&mut *transaction, draft_id)
+            .await
+            .context("Failed to delete the published draft")
+            .map_err(e500)?;
+    }
+
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message().send();
+
+    Ok(response)
+}
+
+fn success_message() -> FlashMessage {
+    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+}
@@ -0,0 +1,87 @@
+use super::get_username;
+use crate::{
+    authentication::{
+        self, has_valid_password_length, validate_credentials, AuthError, Credentials, UserId,
+        MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH,
+    },
+    csrf::CsrfToken,
+    utils::{e500, render_page, see_other},
+};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+
+#[tracing::instrument(name = "Show the change password form", skip_all)]
+pub async fn change_password_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(
+        &template,
+        "admin/password.html",
+        &flash_messages,
+        &csrf_token,
+        TeraContext::new(),
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Change password", skip_all, fields(user_id = %*user_id))]
+pub async fn change_password(
+    form: web::Form<ChangePasswordFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return Ok(password_redirect(FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )));
+    }
+
+    if !has_valid_password_length(&form.new_password) {
+        return Ok(password_redirect(FlashMessage::error(format!(
+            "The new password must be between {MIN_PASSWORD_LENGTH} and \
+            {MAX_PASSWORD_LENGTH} characters long."
+        ))));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => Ok(password_redirect(FlashMessage::error(
+                "The current password is incorrect.",
+            ))),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    authentication::change_password(*user_id, form.0.new_password, &pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(password_redirect(FlashMessage::info(
+        "Your password has been changed.",
+    )))
+}
+
+fn password_redirect(message: FlashMessage) -> HttpResponse {
+    message.send();
+    see_other("/admin/password")
+}
@@ -0,0 +1,212 @@
+use crate::{
+    authentication::UserId,
+    clock::Clock,
+    configuration::SuppressionSalt,
+    csrf::CsrfToken,
+    domain::{Email, SubscriberStatus},
+    erasure::{erase_subscriber, ErasureMode},
+    lists::{all_lists, default_list, requested_list_ids},
+    subscriber_export::{self, subscriber_data, ExportFormat},
+    subscriber_import::{import_subscribers, ImportMode},
+    utils::{e400, e500, render_page, see_other},
+};
+use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+
+#[tracing::instrument(name = "Show the subscriber import form", skip_all)]
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let context = lists_context(&pool).await.map_err(e500)?;
+    render_page(
+        &template,
+        "admin/import.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+/// The lists to pick from, for the import form.
+async fn lists_context(pool: &PgPool) -> Result<TeraContext, anyhow::Error> {
+    let mut context = TeraContext::new();
+    context.insert("lists", &all_lists(pool).await?);
+    context.insert("default_list", &default_list().to_string());
+    Ok(context)
+}
+
+#[derive(MultipartForm)]
+pub struct ImportFormData {
+    file: Bytes,
+    mode: Text<ImportMode>,
+    /// Slug of the list to import into, the default list if omitted.
+    list: Option<Text<String>>,
+}
+
+/// Imports the uploaded CSV file and shows what happened to each of its lines.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers from the admin dashboard",
+    skip_all,
+    fields(user_id = %*user_id, mode = ?form.mode.0)
+)]
+pub async fn upload_subscribers(
+    form: MultipartForm<ImportFormData>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    salt: web::Data<SuppressionSalt>,
+    template: web::Data<Tera>,
+    csrf_token: CsrfToken,
+    user_id: web::ReqData<UserId>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let list = form.list.as_ref().map(|list| list.0.clone());
+    let imported = match requested_list_ids(pool.get_ref(), list.into_iter().collect()).await {
+        Ok(list_ids) => {
+            let file = form.file.data.as_ref();
+            import_subscribers(&pool, file, form.mode.0, list_ids[0], &salt, &clock)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    let report = match imported {
+        Ok(report) => report,
+        Err(e) => {
+            FlashMessage::error(format!("The file could not be imported: {e}")).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+
+    let mut context = lists_context(&pool).await.map_err(e500)?;
+    context.insert("report", &report);
+    context.insert("n_accepted", &report.n_accepted());
+    context.insert("n_already_subscribed", &report.n_already_subscribed());
+    context.insert("n_rejected", &report.n_rejected());
+    render_page(
+        &template,
+        "admin/import.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<String>,
+}
+
+/// Downloads the subscriber list, streamed as it is read from the database.
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip_all,
+    fields(user_id = %*user_id, format = ?params.format)
+)]
+pub async fn export_subscribers(
+    params: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ExportParameters { format, status } = params.into_inner();
+    let status = status
+        .map(|status| status.parse::<SubscriberStatus>())
+        .transpose()
+        .map_err(e400)?;
+
+    let chunks = subscriber_export::export_subscribers(pool.get_ref().clone(), status, format);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(attachment(format!("subscribers.{}", format.extension())))
+        .streaming(chunks))
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberDataParameters {
+    email: String,
+}
+
+/// Everything held on one subscriber as JSON, to answer a data access request.
+#[tracing::instrument(
+    name = "Export a subscriber's data",
+    skip_all,
+    fields(user_id = %*user_id)
+)]
+pub async fn export_subscriber_data(
+    params: web::Query<SubscriberDataParameters>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = Email::parse(params.into_inner().email).map_err(e400)?;
+    let Some(data) = subscriber_data(&pool, &email).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(attachment("subscriber-data.json".into()))
+        .json(data))
+}
+
+fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EraseFormData {
+    email: String,
+    #[serde(default)]
+    mode: ErasureMode,
+}
+
+#[tracing::instrument(
+    name = "Erase a subscriber from the admin dashboard",
+    skip_all,
+    fields(user_id = %*user_id, mode = ?form.mode)
+)]
+pub async fn erase_subscriber_from_form(
+    form: web::Form<EraseFormData>,
+    pool: web::Data<PgPool>,
+    salt: web::Data<SuppressionSalt>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let EraseFormData { email, mode } = form.into_inner();
+    let email = match Email::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/dashboard"));
+        }
+    };
+
+    let erased = erase_subscriber(&pool, &salt, &email, mode, Some(**user_id))
+        .await
+        .map_err(e500)?;
+    let message = if erased.is_empty() {
+        format!(
+            "There is no subscriber with the email address {email}, \
+            it has been added to the suppression list."
+        )
+    } else {
+        format!("{email} has been erased.")
+    };
+    FlashMessage::info(message).send();
+
+    Ok(see_other("/admin/dashboard"))
+}
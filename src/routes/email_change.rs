@@ -0,0 +1,350 @@
+use crate::{
+    clock::Clock,
+    configuration::SubscriptionTokenTtl,
+    domain::{Email, SubscriberName, SubscriberStatus},
+    email_client::EmailClient,
+    routes::{generate_subscription_token, hash_subscription_token, SendMailError},
+    utils::error_chain_fmt,
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+/// Records that the subscriber asked to move to `new_email`, and returns the
+/// token that confirms it. Only the latest request stands: earlier links stop
+/// working.
+#[tracing::instrument(name = "Requesting an email change", skip(tx, ttl, clock))]
+pub async fn request_email_change(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: &Uuid,
+    new_email: &Email,
+    ttl: &SubscriptionTokenTtl,
+    clock: &Clock,
+) -> Result<String, EmailChangeError> {
+    if is_email_taken(&mut **tx, subscriber_id, new_email)
+        .await
+        .context("Failed to look up the new email address")?
+    {
+        return Err(EmailChangeError::EmailTaken(new_email.clone()));
+    }
+    cancel_email_change(&mut **tx, subscriber_id)
+        .await
+        .context("Failed to cancel the previous email change")?;
+    let token = store_email_change_token(tx, subscriber_id, new_email, ttl, clock)
+        .await
+        .context("Failed to store the email change token")?;
+
+    Ok(token)
+}
+
+/// Drops the subscriber's pending email change, if any.
+#[tracing::instrument(name = "Cancelling an email change", skip(executor))]
+pub async fn cancel_email_change(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: &Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND new_email IS NOT NULL",
+        subscriber_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether another subscriber uses the address, in whatever case.
+#[tracing::instrument(name = "Checking if an email address is taken", skip(executor))]
+async fn is_email_taken(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: &Uuid,
+    email: &Email,
+) -> Result<bool, sqlx::Error> {
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscriptions WHERE lower(email) = lower($2) AND id <> $1
+        ) AS "taken!"
+        "#,
+        subscriber_id,
+        email.as_ref()
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(taken)
+}
+
+/// Like [`store_token`](super::store_token), for a token that confirms the
+/// switch to `new_email` rather than the subscription itself.
+#[tracing::instrument(name = "Persisting email change token", skip(tx, ttl, clock))]
+async fn store_email_change_token(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: &Uuid,
+    new_email: &Email,
+    ttl: &SubscriptionTokenTtl,
+    clock: &Clock,
+) -> Result<String, sqlx::Error> {
+    let now = clock.now();
+    let token = generate_subscription_token();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (
+            subscription_token_hash,
+            subscriber_id,
+            created_at,
+            expires_at,
+            new_email
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        hash_subscription_token(&token),
+        subscriber_id,
+        now,
+        now + ttl.0,
+        new_email.as_ref()
+    );
+    tx.execute(query).await?;
+
+    Ok(token)
+}
+
+#[derive(Serialize)]
+struct EmailChangeContext<'a> {
+    name: &'a str,
+    new_email: &'a str,
+    link: String,
+}
+
+/// Sends the confirmation link to the new address, and lets the current one
+/// know that a change was asked for, in case it wasn't them.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Sending the email change emails",
+    skip(email_client, template, name, base_url, preferences_link, token)
+)]
+pub async fn send_email_change_emails(
+    email_client: &EmailClient,
+    template: &Tera,
+    name: &SubscriberName,
+    old_email: &Email,
+    new_email: &Email,
+    base_url: &str,
+    preferences_link: &str,
+    token: &str,
+) -> Result<(), SendMailError> {
+    let confirmation = EmailChangeContext {
+        name: name.as_ref(),
+        new_email: new_email.as_ref(),
+        link: format!("{base_url}/preferences/confirm-email?token={token}"),
+    };
+    let confirmation = TeraContext::from_serialize(&confirmation)?;
+    email_client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &template.render("confirm-new-email.html", &confirmation)?,
+            &template.render("confirm-new-email.txt", &confirmation)?,
+        )
+        .await?;
+
+    let notice = EmailChangeContext {
+        name: name.as_ref(),
+        new_email: new_email.as_ref(),
+        link: preferences_link.to_string(),
+    };
+    let notice = TeraContext::from_serialize(&notice)?;
+    email_client
+        .send_email(
+            old_email,
+            "Your email address is about to change",
+            &template.render("email-change-notice.html", &notice)?,
+            &template.render("email-change-notice.txt", &notice)?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailChangeParameters {
+    token: String,
+}
+
+struct EmailChange {
+    subscriber_id: Uuid,
+    new_email: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Confirm an email change", skip(db, params, clock))]
+pub async fn confirm_email_change(
+    db: web::Data<PgPool>,
+    params: web::Query<ConfirmEmailChangeParameters>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, EmailChangeError> {
+    let mut tx = db
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    // Locked so that a second click waits for the first one, then finds the
+    // token gone
+    let change = sqlx::query_as!(
+        EmailChange,
+        r#"
+        SELECT subscriber_id, new_email AS "new_email!", expires_at
+        FROM subscription_tokens
+        WHERE subscription_token_hash = $1 AND new_email IS NOT NULL
+        FOR UPDATE
+        "#,
+        hash_subscription_token(&params.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to look up the token")?
+    .ok_or(EmailChangeError::InvalidLink)?;
+    if change.expires_at <= clock.now() {
+        return Err(EmailChangeError::TokenExpired);
+    }
+    let new_email = Email::parse(change.new_email)
+        .map_err(anyhow::Error::msg)
+        .context("Invalid email address stored in the token")?;
+
+    switch_email(&mut tx, &change.subscriber_id, &new_email).await?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Moves the subscriber, and their deliveries, to the new address, all in
+/// the caller's transaction. The address may have been taken since the change
+/// was asked for: it is checked again, and the unique constraint on
+/// `subscriptions.email` has the last word.
+///
+/// Only confirmed subscribers can switch: a link asked for before leaving
+/// doesn't work anymore.
+#[tracing::instrument(name = "Switching email address", skip(tx))]
+async fn switch_email(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: &Uuid,
+    new_email: &Email,
+) -> Result<(), EmailChangeError> {
+    let old_email = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1 AND status = $2 FOR UPDATE",
+        subscriber_id,
+        SubscriberStatus::Ok.to_string()
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or(EmailChangeError::InvalidLink)?;
+    if is_email_taken(&mut **tx, subscriber_id, new_email)
+        .await
+        .context("Failed to look up the new email address")?
+    {
+        return Err(EmailChangeError::EmailTaken(new_email.clone()));
+    }
+
+    tx.execute(sqlx::query!(
+        "UPDATE subscriptions SET email = $2 WHERE id = $1",
+        subscriber_id,
+        new_email.as_ref()
+    ))
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => {
+            EmailChangeError::EmailTaken(new_email.clone())
+        }
+        _ => anyhow::Error::new(e)
+            .context("Failed to update the email address")
+            .into(),
+    })?;
+    move_deliveries(tx, &old_email, new_email)
+        .await
+        .context("Failed to move the deliveries to the new address")?;
+    cancel_email_change(&mut **tx, subscriber_id)
+        .await
+        .context("Failed to delete the email change tokens")?;
+
+    Ok(())
+}
+
+/// Deliveries are keyed by address rather than by subscriber, in whatever
+/// case it was written when they were queued.
+async fn move_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    old_email: &str,
+    new_email: &Email,
+) -> Result<(), sqlx::Error> {
+    tx.execute(sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET subscriber_email = $2
+        WHERE lower(subscriber_email) = lower($1)
+        "#,
+        old_email,
+        new_email.as_ref()
+    ))
+    .await?;
+    tx.execute(sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET subscriber_email = $2
+        WHERE lower(subscriber_email) = lower($1)
+        "#,
+        old_email,
+        new_email.as_ref()
+    ))
+    .await?;
+    tx.execute(sqlx::query!(
+        r#"
+        UPDATE issue_delivery_dead_letters
+        SET subscriber_email = $2
+        WHERE lower(subscriber_email) = lower($1)
+        "#,
+        old_email,
+        new_email.as_ref()
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailChangeError {
+    #[error("The link is invalid.")]
+    InvalidLink,
+
+    #[error("The confirmation link has expired.")]
+    TokenExpired,
+
+    #[error("{0} is already subscribed.")]
+    EmailTaken(Email),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EmailChangeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::TokenExpired => StatusCode::GONE,
+            Self::EmailTaken(_) => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
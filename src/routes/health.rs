@@ -1,9 +1,139 @@
-use actix_web::HttpResponse;
+use std::{future::Future, time::Duration, time::Instant};
 
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::{Executor, PgPool};
+
+use crate::{
+    configuration::ReadinessSettings, email_client::EmailClient, migrations::pending_migrations,
+};
+
+/// Liveness: the process is up and serving requests.
 pub async fn ping() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize)]
+struct CheckReport {
+    name: &'static str,
+    status: CheckStatus,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReadinessReport {
+    status: CheckStatus,
+    checks: Vec<CheckReport>,
+}
+
+/// Readiness: the dependencies needed to serve requests are available.
+/// Checks run concurrently and each of them is bounded by the configured timeout.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<ReadinessSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+
+    let email = async {
+        if settings.check_email_transport {
+            let ping = async {
+                email_client
+                    .ping()
+                    .await
+                    .context("Failed to reach the email transport")
+            };
+            Some(run_check("email", timeout, ping).await)
+        } else {
+            None
+        }
+    };
+    let (database, migrations, email) = tokio::join!(
+        run_check("database", timeout, check_database(&pool)),
+        run_check("migrations", timeout, check_migrations(&pool)),
+        email,
+    );
+
+    let checks: Vec<_> = [Some(database), Some(migrations), email]
+        .into_iter()
+        .flatten()
+        .collect();
+    let status = if checks.iter().all(|check| check.status == CheckStatus::Ok) {
+        CheckStatus::Ok
+    } else {
+        CheckStatus::Error
+    };
+
+    let mut response = match status {
+        CheckStatus::Ok => HttpResponse::Ok(),
+        CheckStatus::Error => HttpResponse::ServiceUnavailable(),
+    };
+    response.json(ReadinessReport { status, checks })
+}
+
+async fn run_check(
+    name: &'static str,
+    timeout: Duration,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out after {:?}", timeout)));
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    match outcome {
+        Ok(()) => CheckReport {
+            name,
+            status: CheckStatus::Ok,
+            latency_ms,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                check = name,
+                "Readiness check failed",
+            );
+            CheckReport {
+                name,
+                status: CheckStatus::Error,
+                latency_ms,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    pool.execute("SELECT 1")
+        .await
+        .context("Failed to query the database")?;
+
+    Ok(())
+}
+
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let pending = pending_migrations(pool).await?;
+    if !pending.is_empty() {
+        anyhow::bail!("Migrations {:?} are not applied", pending);
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::ping;
@@ -0,0 +1,90 @@
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    csrf::CsrfToken,
+    session::TypedSession,
+    utils::{e500, error_chain_fmt, render_page, see_other},
+};
+use actix_web::{error::InternalError, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+
+#[tracing::instrument(name = "Show the login form", skip_all)]
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(
+        &template,
+        "login.html",
+        &flash_messages,
+        &csrf_token,
+        TeraContext::new(),
+    )
+    .map_err(e500)
+}
+
+#[derive(Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(
+    name = "Log in",
+    skip(form, pool, session),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<LoginFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+            login_redirect(e)
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    session.renew();
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Sends the user back to the login form, with the error as a flash message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other("/login"))
+}
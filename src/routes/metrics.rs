@@ -0,0 +1,13 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::utils::e500;
+
+/// Exposes the application metrics to Prometheus.
+pub async fn metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let body = crate::metrics::render(&pool).map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
@@ -1,9 +1,23 @@
+mod admin;
+mod email_change;
 mod health;
+mod login;
+mod metrics;
 mod newsletters;
+mod preferences;
+mod scheduled_newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod unsubscribe;
 
+pub use admin::*;
+pub use email_change::*;
 pub use health::*;
+pub use login::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use preferences::*;
+pub use scheduled_newsletters::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use unsubscribe::*;
@@ -1,18 +1,35 @@
 use crate::{
-    domain::{Email, SubscriberStatus},
-    email_client::EmailClient,
+    authentication::{validate_credentials, AuthError, Credentials},
+    clock::Clock,
+    domain::{DeliveryFrequency, SendTime, SubscriberStatus},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    lists::{requested_list_ids, ListError},
     utils::error_chain_fmt,
 };
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{
+    http::header::{self, HeaderMap, HeaderValue},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
 use anyhow::Context;
-use rayon::prelude::*;
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
-use sqlx::PgPool;
+use secrecy::Secret;
+use serde::Serialize;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct NewsletterPublishDTO {
     title: String,
     content: Content,
+    /// Slugs of the lists to send the issue to, the default list if empty.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Sends the issue later rather than right away, see [`SendTime`].
+    scheduled_at: Option<String>,
+    /// IANA timezone of `scheduled_at`, when it carries no offset.
+    timezone: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -23,6 +40,18 @@ pub struct Content {
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+
+    #[error("There is no newsletter issue {0}.")]
+    UnknownIssue(Uuid),
+
+    #[error("The newsletter issue {0} has already been sent.")]
+    AlreadySent(Uuid),
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -33,73 +62,258 @@ impl std::fmt::Debug for PublishError {
     }
 }
 
+impl From<ListError> for PublishError {
+    fn from(value: ListError) -> Self {
+        match value {
+            ListError::UnexpectedError(e) => Self::UnexpectedError(e),
+            e => Self::ValidationError(e.to_string()),
+        }
+    }
+}
+
 impl ResponseError for PublishError {
-    fn status_code(&self) -> reqwest::StatusCode {
+    fn error_response(&self) -> HttpResponse {
         match self {
-            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            Self::UnknownIssue(_) => HttpResponse::new(StatusCode::NOT_FOUND),
+            Self::AlreadySent(_) => HttpResponse::new(StatusCode::CONFLICT),
+            Self::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Self::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
         }
     }
 }
 
+/// Tells the caller of a scheduled issue how to refer to it later on.
+#[derive(Serialize)]
+pub struct ScheduledIssue {
+    pub newsletter_issue_id: Uuid,
+    pub scheduled_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, clock, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     body: web::Json<NewsletterPublishDTO>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    clock: web::Data<Clock>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| format!("Failed to send email to {}", subscriber.email))?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                )
-            }
+    let user_id = authenticate(&request, &pool).await?;
+
+    let idempotency_key = idempotency_key(request.headers())?;
+    let list_ids = requested_list_ids(pool.get_ref(), body.lists.clone()).await?;
+    let send_time = body
+        .scheduled_at
+        .as_deref()
+        .map(|at| SendTime::parse(at, body.timezone.as_deref(), clock.now()))
+        .transpose()
+        .map_err(PublishError::ValidationError)?
+        .map(DateTime::<Utc>::from);
+    let mut transaction = match try_processing(&pool, &idempotency_key, user_id).await? {
+        NextAction::StartProcessing(transaction) => *transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        &list_ids,
+        send_time,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    let response = match send_time {
+        Some(scheduled_at) => HttpResponse::Accepted().json(ScheduledIssue {
+            newsletter_issue_id: issue_id,
+            scheduled_at,
+        }),
+        None => {
+            publish_issue(&mut transaction, issue_id, clock.now())
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            HttpResponse::Accepted().finish()
         }
-    }
+    };
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(response)
 }
 
-struct ConfirmedSubscriber {
-    pub email: Email,
+/// Checks the caller's `Basic` credentials, for the endpoints that manage
+/// newsletter issues.
+pub(super) async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, PublishError> {
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let user_id = validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    Ok(user_id)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-pub async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let rows = sqlx::query!(
+fn idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, PublishError> {
+    let header_value = headers
+        .get("Idempotency-Key")
+        .ok_or_else(|| {
+            PublishError::ValidationError("The 'Idempotency-Key' header was missing".into())
+        })?
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError(
+                "The 'Idempotency-Key' header was not a valid UTF8 string".into(),
+            )
+        })?;
+
+    IdempotencyKey::parse(header_value.to_string()).map_err(PublishError::ValidationError)
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth")?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+/// Stores the issue along with the lists it goes to. It is published right
+/// away with [`publish_issue`], unless scheduled for later, in which case the
+/// scheduler publishes it when `scheduled_at` comes.
+#[tracing::instrument(name = "Storing newsletter issue", skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    list_ids: &[Uuid],
+    scheduled_at: Option<DateTime<Utc>>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            scheduled_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        scheduled_at
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
         r#"
-        SELECT email
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, unnest($2::uuid[])
+        "#,
+        newsletter_issue_id,
+        list_ids
+    );
+    transaction.execute(query).await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Marks the issue as published at `now` and queues its deliveries.
+#[tracing::instrument(name = "Publishing newsletter issue", skip(transaction))]
+pub async fn publish_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = $2 WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+        now
+    );
+    transaction.execute(query).await?;
+
+    enqueue_delivery_tasks(transaction, newsletter_issue_id, now).await
+}
+
+/// Queues one delivery per confirmed subscriber of any of the issue's lists,
+/// however many of them they are a member of. Subscribers on a break are
+/// skipped, and those who asked for a digest get the issue with their next one.
+#[tracing::instrument(name = "Enqueueing delivery tasks", skip(transaction))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email,
+            next_attempt_at
+        )
+        SELECT DISTINCT
+            $1::uuid,
+            subscriptions.email,
+            CASE subscriptions.delivery_frequency
+                WHEN $3 THEN $4::timestamptz
+                WHEN $5 THEN $6::timestamptz
+                ELSE $7::timestamptz
+            END
         FROM subscriptions
-        WHERE status = $1
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        JOIN newsletter_issue_lists ON newsletter_issue_lists.list_id = list_memberships.list_id
+        WHERE newsletter_issue_lists.newsletter_issue_id = $1
+            AND subscriptions.status = $2
+            AND list_memberships.status = $2
+            AND (subscriptions.paused_until IS NULL OR subscriptions.paused_until <= $7)
         "#,
-        SubscriberStatus::Ok.to_string()
-    )
-    .fetch_all(pool)
-    .await?;
-
-    let confirmed_subscribers = rows
-        .into_par_iter()
-        .map(|r| match Email::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
-            Err(error) => Err(anyhow::anyhow!(error)),
-        })
-        .collect();
-
-    Ok(confirmed_subscribers)
+        newsletter_issue_id,
+        SubscriberStatus::Ok.to_string(),
+        DeliveryFrequency::Daily.to_string(),
+        DeliveryFrequency::Daily.next_delivery_at(now),
+        DeliveryFrequency::Weekly.to_string(),
+        DeliveryFrequency::Weekly.next_delivery_at(now),
+        now
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
 }
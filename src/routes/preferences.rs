@@ -0,0 +1,484 @@
+use crate::{
+    clock::Clock,
+    configuration::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenTtl},
+    csrf::CsrfToken,
+    domain::{DeliveryFrequency, Email, SubscriberName, SubscriberStatus},
+    email_client::EmailClient,
+    lists::add_membership,
+    routes::{
+        cancel_email_change, request_email_change, send_email_change_emails, unsubscribe_link,
+        EmailChangeError,
+    },
+    utils::{error_chain_fmt, render_page, see_other},
+};
+use actix_web::{web, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+/// Longest break a subscriber can take in one go.
+const MAX_PAUSE_WEEKS: u32 = 52;
+
+#[derive(Deserialize)]
+pub struct PreferencesParameters {
+    subscriber_id: Uuid,
+    tag: String,
+}
+
+impl PreferencesParameters {
+    fn verify(&self, secret: &HmacSecret) -> Result<Uuid, PreferencesError> {
+        let tag = hex::decode(&self.tag).map_err(|_| PreferencesError::InvalidLink)?;
+
+        preferences_mac(secret, &self.subscriber_id)
+            .verify_slice(&tag)
+            .map_err(|_| PreferencesError::InvalidLink)?;
+
+        Ok(self.subscriber_id)
+    }
+}
+
+fn preferences_mac(secret: &HmacSecret, subscriber_id: &Uuid) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // Keeps an unsubscribe tag from being used as a preferences one, and the other way round
+    mac.update(b"preferences:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+/// Builds the signed link to the subscriber's preferences page, which needs
+/// no login: like the unsubscribe link, it is verified by recomputing its tag.
+pub fn preferences_link(base_url: &str, secret: &HmacSecret, subscriber_id: &Uuid) -> String {
+    format!("{base_url}{}", preferences_path(secret, subscriber_id))
+}
+
+fn preferences_path(secret: &HmacSecret, subscriber_id: &Uuid) -> String {
+    let tag = hex::encode(
+        preferences_mac(secret, subscriber_id)
+            .finalize()
+            .into_bytes(),
+    );
+    format!("/preferences?subscriber_id={subscriber_id}&tag={tag}")
+}
+
+struct Subscriber {
+    name: String,
+    email: String,
+    delivery_frequency: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct ListChoice {
+    slug: String,
+    name: String,
+    subscribed: bool,
+}
+
+#[derive(Serialize)]
+struct PreferencesPageContext {
+    name: String,
+    email: String,
+    frequency: String,
+    frequencies: Vec<String>,
+    paused_until: Option<String>,
+    lists: Vec<ListChoice>,
+    link: String,
+    unsubscribe_link: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Show the preferences page",
+    skip(
+        params,
+        db,
+        secret,
+        base_url,
+        flash_messages,
+        csrf_token,
+        template,
+        clock
+    )
+)]
+pub async fn preferences_form(
+    params: web::Query<PreferencesParameters>,
+    db: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    template: web::Data<Tera>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = params.verify(&secret)?;
+    let subscriber = get_subscriber(&db, &subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(PreferencesError::SubscriberDoesNotExist)?;
+    let lists = get_list_choices(&db, &subscriber_id)
+        .await
+        .context("Failed to fetch the lists")?;
+
+    let context = PreferencesPageContext {
+        name: subscriber.name,
+        email: subscriber.email,
+        frequency: subscriber.delivery_frequency,
+        frequencies: [
+            DeliveryFrequency::Immediate,
+            DeliveryFrequency::Daily,
+            DeliveryFrequency::Weekly,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect(),
+        paused_until: subscriber
+            .paused_until
+            .filter(|paused_until| *paused_until > clock.now())
+            .map(|paused_until| paused_until.format("%B %-d, %Y").to_string()),
+        lists,
+        link: preferences_path(&secret, &subscriber_id),
+        unsubscribe_link: unsubscribe_link(&base_url.0, &secret, &subscriber_id),
+    };
+    let body = render_page(
+        &template,
+        "preferences.html",
+        &flash_messages,
+        &csrf_token,
+        TeraContext::from_serialize(&context).context("Failed to build the page context")?,
+    )?;
+
+    Ok(body)
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(db))]
+async fn get_subscriber(
+    db: &PgPool,
+    subscriber_id: &Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT name, email, delivery_frequency, paused_until
+        FROM subscriptions
+        WHERE id = $1 AND status = $2
+        "#,
+        subscriber_id,
+        SubscriberStatus::Ok.to_string()
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Every list, ticked when the subscriber is on it or waiting to be.
+#[tracing::instrument(name = "Get the subscriber's lists", skip(db))]
+async fn get_list_choices(
+    db: &PgPool,
+    subscriber_id: &Uuid,
+) -> Result<Vec<ListChoice>, sqlx::Error> {
+    sqlx::query_as!(
+        ListChoice,
+        r#"
+        SELECT
+            lists.slug,
+            lists.name,
+            COALESCE(list_memberships.status IN ($2, $3), false) AS "subscribed!"
+        FROM lists
+        LEFT JOIN list_memberships
+            ON list_memberships.list_id = lists.list_id
+            AND list_memberships.subscriber_id = $1
+        ORDER BY lists.name
+        "#,
+        subscriber_id,
+        SubscriberStatus::Ok.to_string(),
+        SubscriberStatus::PendingConfirmation.to_string()
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email: String,
+    /// One checkbox per list, hence a field repeated in the form.
+    #[serde(default)]
+    lists: Vec<String>,
+    frequency: DeliveryFrequency,
+    /// Left empty to keep the current pause, 0 to resume deliveries.
+    pause_weeks: Option<u32>,
+}
+
+struct Preferences {
+    name: SubscriberName,
+    email: Email,
+    lists: Vec<String>,
+    frequency: DeliveryFrequency,
+    pause_weeks: Option<u32>,
+}
+
+impl TryFrom<PreferencesFormData> for Preferences {
+    type Error = String;
+
+    fn try_from(value: PreferencesFormData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name)?;
+        let email = Email::parse(value.email)?;
+        if value
+            .pause_weeks
+            .is_some_and(|weeks| weeks > MAX_PAUSE_WEEKS)
+        {
+            return Err(format!(
+                "Deliveries can be paused for up to {MAX_PAUSE_WEEKS} weeks."
+            ));
+        }
+
+        Ok(Self {
+            name,
+            email,
+            lists: value.lists,
+            frequency: value.frequency,
+            pause_weeks: value.pause_weeks,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(
+        params,
+        body,
+        db,
+        secret,
+        base_url,
+        email_client,
+        template,
+        token_ttl,
+        clock
+    )
+)]
+pub async fn update_preferences(
+    params: web::Query<PreferencesParameters>,
+    body: web::Bytes,
+    db: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    email_client: web::Data<EmailClient>,
+    template: web::Data<Tera>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = params.verify(&secret)?;
+    let back = see_other(&preferences_path(&secret, &subscriber_id));
+    let preferences = match serde_html_form::from_bytes::<PreferencesFormData>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(Preferences::try_from)
+    {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(back);
+        }
+    };
+
+    let mut tx = db
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    let subscriber = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1 AND status = $2 FOR UPDATE",
+        subscriber_id,
+        SubscriberStatus::Ok.to_string()
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to fetch the subscriber")?
+    .ok_or(PreferencesError::SubscriberDoesNotExist)?;
+
+    let paused_until = match preferences.pause_weeks {
+        None => None,
+        Some(0) => Some(None),
+        Some(weeks) => Some(Some(clock.now() + Duration::weeks(weeks.into()))),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            delivery_frequency = $3,
+            paused_until = CASE WHEN $4 THEN $5 ELSE paused_until END
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        preferences.name.as_ref(),
+        preferences.frequency.to_string(),
+        paused_until.is_some(),
+        paused_until.flatten()
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to update the subscriber")?;
+    update_list_memberships(&mut tx, &subscriber_id, &preferences.lists)
+        .await
+        .context("Failed to update the list memberships")?;
+
+    let email_change = if preferences.email.as_ref() == subscriber.email {
+        // Saving with the current address is how a change someone else asked
+        // for gets called off
+        cancel_email_change(&mut *tx, &subscriber_id)
+            .await
+            .context("Failed to cancel the email change")?;
+        None
+    } else {
+        match request_email_change(
+            &mut tx,
+            &subscriber_id,
+            &preferences.email,
+            &token_ttl,
+            &clock,
+        )
+        .await
+        {
+            Ok(token) => Some(token),
+            Err(e @ EmailChangeError::EmailTaken(_)) => {
+                FlashMessage::error(e.to_string()).send();
+                return Ok(back);
+            }
+            Err(e) => return Err(anyhow::Error::new(e).into()),
+        }
+    };
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    if let Some(token) = email_change {
+        let old_email = Email::parse(subscriber.email)
+            .map_err(anyhow::Error::msg)
+            .context("Invalid email address stored for the subscriber")?;
+        send_email_change_emails(
+            &email_client,
+            &template,
+            &preferences.name,
+            &old_email,
+            &preferences.email,
+            &base_url.0,
+            &preferences_link(&base_url.0, &secret, &subscriber_id),
+            &token,
+        )
+        .await
+        .context("Failed to send the email change emails")?;
+        FlashMessage::info(format!(
+            "Your preferences have been saved. \
+            Follow the link we sent to {} to start using it.",
+            preferences.email
+        ))
+        .send();
+    } else {
+        FlashMessage::info("Your preferences have been saved.").send();
+    }
+
+    Ok(back)
+}
+
+/// Brings the subscriber onto the ticked lists and off the others. Following
+/// a link sent to their address already proves it is theirs, so joining a
+/// list from here needs no further confirmation.
+#[tracing::instrument(name = "Updating list memberships", skip(tx))]
+async fn update_list_memberships(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: &Uuid,
+    slugs: &[String],
+) -> Result<(), sqlx::Error> {
+    let list_ids = sqlx::query_scalar!("SELECT list_id FROM lists WHERE slug = ANY($1)", slugs)
+        .fetch_all(&mut **tx)
+        .await?;
+    for list_id in &list_ids {
+        add_membership(&mut **tx, *list_id, *subscriber_id, SubscriberStatus::Ok).await?;
+    }
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $3
+        WHERE subscriber_id = $1 AND list_id <> ALL($2)
+        "#,
+        subscriber_id,
+        &list_ids[..],
+        SubscriberStatus::Unsubscribed.to_string()
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The link is invalid.")]
+    InvalidLink,
+
+    #[error("You are not subscribed.")]
+    SubscriberDoesNotExist,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::SubscriberDoesNotExist => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{preferences_link, PreferencesParameters};
+    use crate::{configuration::HmacSecret, routes::unsubscribe_link};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("secret".to_string()))
+    }
+
+    fn params_from_link(link: &str) -> PreferencesParameters {
+        let query = link.split_once('?').unwrap().1;
+        actix_web::web::Query::<PreferencesParameters>::from_query(query)
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn a_generated_link_is_valid() {
+        let subscriber_id = Uuid::new_v4();
+        let link = preferences_link("http://localhost", &secret(), &subscriber_id);
+
+        let verified = assert_ok!(params_from_link(&link).verify(&secret()));
+        assert_eq!(verified, subscriber_id);
+    }
+
+    #[test]
+    fn an_unsubscribe_link_does_not_open_the_preferences() {
+        let link = unsubscribe_link("http://localhost", &secret(), &Uuid::new_v4());
+
+        assert_err!(params_from_link(&link).verify(&secret()));
+    }
+}
@@ -0,0 +1,127 @@
+use super::newsletters::authenticate;
+use crate::{
+    clock::Clock,
+    domain::SendTime,
+    routes::{PublishError, ScheduledIssue},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleDTO {
+    scheduled_at: String,
+    timezone: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(body, pool, clock, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleDTO>,
+    pool: web::Data<PgPool>,
+    clock: web::Data<Clock>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let scheduled_at: DateTime<Utc> =
+        SendTime::parse(&body.scheduled_at, body.timezone.as_deref(), clock.now())
+            .map_err(PublishError::ValidationError)?
+            .into();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    lock_scheduled_issue(&mut transaction, newsletter_issue_id).await?;
+    transaction
+        .execute(sqlx::query!(
+            "UPDATE newsletter_issues SET scheduled_at = $2 WHERE newsletter_issue_id = $1",
+            newsletter_issue_id,
+            scheduled_at
+        ))
+        .await
+        .context("Failed to reschedule the newsletter issue")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::Ok().json(ScheduledIssue {
+        newsletter_issue_id,
+        scheduled_at,
+    }))
+}
+
+/// Drops a scheduled issue altogether: nothing about it is kept.
+#[tracing::instrument(
+    name = "Cancel a newsletter issue",
+    skip(pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn cancel_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    lock_scheduled_issue(&mut transaction, newsletter_issue_id).await?;
+    transaction
+        .execute(sqlx::query!(
+            "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+            newsletter_issue_id
+        ))
+        .await
+        .context("Failed to delete the lists of the newsletter issue")?;
+    transaction
+        .execute(sqlx::query!(
+            "DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1",
+            newsletter_issue_id
+        ))
+        .await
+        .context("Failed to delete the newsletter issue")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Makes sure the issue is still waiting for its send time, and keeps the
+/// scheduler from publishing it until the transaction is over.
+async fn lock_scheduled_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), PublishError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the newsletter issue")?
+    .ok_or(PublishError::UnknownIssue(newsletter_issue_id))?;
+
+    match issue.published_at {
+        Some(_) => Err(PublishError::AlreadySent(newsletter_issue_id)),
+        None => Ok(()),
+    }
+}
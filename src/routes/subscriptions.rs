@@ -1,15 +1,24 @@
-use crate::configuration::ApplicationBaseUrl;
-use crate::domain::{Email, SubscriberStatus};
-use crate::utils::error_chain_fmt;
-use crate::{domain::NewSubscriber, email_client::EmailClient};
-use actix_web::{web, HttpResponse, ResponseError};
+use crate::clock::Clock;
+use crate::configuration::{ApplicationBaseUrl, ConfirmationResendCooldown, SubscriptionTokenTtl};
+use crate::csrf::CsrfToken;
+use crate::domain::{ConsentEvent, Email, ListSlug, SubscriberStatus};
+use crate::lists::{add_membership, all_lists, default_list, find_list_id, ListError};
+use crate::metrics::{record_funnel_step, FunnelStep};
+use crate::rate_limit::TooManyRequests;
+use crate::utils::{e500, error_chain_fmt, render_page};
+use crate::{
+    domain::NewSubscriber,
+    email_client::{EmailClient, SendEmailError},
+};
+use actix_web::{http::header::ContentType, web, HttpResponse, ResponseError};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
-use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use sqlx::{Executor, PgPool, Postgres, Row, Transaction};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Row, Transaction};
 use tera::{Context as TeraContext, Tera};
 use uuid::Uuid;
 
@@ -17,14 +26,39 @@ use uuid::Uuid;
 pub struct SubscribeFormBody {
     pub name: String,
     pub email: String,
+    /// Slug of the list to join, the default list if omitted.
+    pub list: Option<String>,
 }
 
+#[tracing::instrument(name = "Show the subscription form", skip_all)]
+pub async fn subscribe_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    db: web::Data<PgPool>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = TeraContext::new();
+    context.insert("lists", &all_lists(&db).await.map_err(e500)?);
+    context.insert("default_list", &default_list().to_string());
+
+    render_page(
+        &template,
+        "subscribe.html",
+        &flash_messages,
+        &csrf_token,
+        context,
+    )
+    .map_err(e500)
+}
+
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(body, db, email, base_url, template),
+    skip(body, db, email, base_url, template, token_ttl, resend_cooldown, clock),
     fields(
         subscriber_email = %body.email,
         subscriber_name = %body.name,
+        list = ?body.list,
     ),
 )]
 pub async fn subscribe(
@@ -33,18 +67,45 @@ pub async fn subscribe(
     email: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
     template: web::Data<Tera>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    resend_cooldown: web::Data<ConfirmationResendCooldown>,
+    clock: web::Data<Clock>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let list = match body.list.clone() {
+        Some(list) => ListSlug::parse(list)?,
+        None => default_list(),
+    };
     let new_subscriber: NewSubscriber = body.0.try_into()?;
     let mut tx = db
         .begin()
         .await
         .context("Failed to get a connection from Postgres pool")?;
 
-    let subscriber_id = insert_subscriber(&mut tx, &new_subscriber)
+    let list_id = find_list_id(&mut *tx, &list).await?;
+    let subscriber_id = insert_subscriber(&mut tx, &new_subscriber, &clock)
         .await
         .context("Failed to insert new subscriber".to_string())?;
+    add_membership(
+        &mut *tx,
+        list_id,
+        subscriber_id,
+        SubscriberStatus::PendingConfirmation,
+    )
+    .await
+    .context("Failed to add the subscriber to the list")?;
+    record_consent_event(&mut *tx, &subscriber_id, ConsentEvent::Subscribed, &clock)
+        .await
+        .context("Failed to record the subscription")?;
+
+    if let Some(retry_after) =
+        remaining_resend_cooldown(&mut tx, &subscriber_id, &resend_cooldown, &clock)
+            .await
+            .context("Failed to check when the last confirmation email was sent")?
+    {
+        return Err(TooManyRequests { retry_after }.into());
+    }
 
-    let subscription_token = store_token(&mut tx, &subscriber_id)
+    let subscription_token = store_token(&mut tx, &subscriber_id, &token_ttl, &clock)
         .await
         .context("Failed to store confirmation token")?;
 
@@ -61,33 +122,33 @@ pub async fn subscribe(
     )
     .await
     .context("Failed to send confirmation email")?;
+    record_funnel_step(FunnelStep::Subscribed);
 
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Persisting subscription token in database", skip(tx))]
+/// Issues a fresh token for the subscriber. Only its hash is persisted, so the
+/// returned token has to be sent out right away: it can't be recovered later.
+#[tracing::instrument(
+    name = "Persisting subscription token in database",
+    skip(tx, ttl, clock)
+)]
 pub async fn store_token(
     tx: &mut Transaction<'_, Postgres>,
     subscriber_id: &Uuid,
+    ttl: &SubscriptionTokenTtl,
+    clock: &Clock,
 ) -> Result<String, StoreTokenError> {
-    let token_already_exists_query = sqlx::query!(
-        "SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1",
-        subscriber_id
-    );
-    if let Some(record) = tx
-        .fetch_optional(token_already_exists_query)
-        .await
-        .map_err(StoreTokenError)?
-    {
-        return Ok(record.get("subscription_token"));
-    };
-
+    let now = clock.now();
     let token = generate_subscription_token();
 
     let query = sqlx::query!(
-        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)",
-        token,
-        subscriber_id
+        "INSERT INTO subscription_tokens (subscription_token_hash, subscriber_id, created_at, expires_at) \
+                              VALUES ($1, $2, $3, $4)",
+        hash_subscription_token(&token),
+        subscriber_id,
+        now,
+        now + ttl.0
     );
 
     tx.execute(query).await.map_err(StoreTokenError)?;
@@ -95,6 +156,40 @@ pub async fn store_token(
     Ok(token)
 }
 
+/// How long to wait before another confirmation email can be sent to the
+/// subscriber, if one with a still valid link was sent recently.
+#[tracing::instrument(
+    name = "Checking the confirmation resend cooldown",
+    skip(tx, cooldown, clock)
+)]
+async fn remaining_resend_cooldown(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: &Uuid,
+    cooldown: &ConfirmationResendCooldown,
+    clock: &Clock,
+) -> Result<Option<std::time::Duration>, sqlx::Error> {
+    let now = clock.now();
+    let query = sqlx::query!(
+        r#"
+        SELECT max(created_at) AS last_sent_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1 AND expires_at > $2 AND new_email IS NULL
+        "#,
+        subscriber_id,
+        now
+    );
+    let last_sent_at = query.fetch_one(&mut **tx).await?.last_sent_at;
+
+    Ok(last_sent_at
+        .map(|last_sent_at| last_sent_at + cooldown.0 - now)
+        .and_then(|remaining| remaining.to_std().ok())
+        .filter(|remaining| !remaining.is_zero()))
+}
+
+pub fn hash_subscription_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
@@ -119,10 +214,11 @@ pub async fn does_subscriber_exist(
     }
 }
 
-#[tracing::instrument(name = "Persisting subscriber to database", skip(tx, body))]
+#[tracing::instrument(name = "Persisting subscriber to database", skip(tx, body, clock))]
 pub async fn insert_subscriber(
     tx: &mut Transaction<'_, Postgres>,
     body: &NewSubscriber,
+    clock: &Clock,
 ) -> Result<Uuid, sqlx::Error> {
     let new_subscriber_id = match does_subscriber_exist(tx, &body.email).await? {
         Some(id) => return Ok(id),
@@ -133,7 +229,7 @@ pub async fn insert_subscriber(
         new_subscriber_id,
         body.email.as_ref(),
         body.name.as_ref(),
-        Utc::now(),
+        clock.now(),
         SubscriberStatus::PendingConfirmation.to_string()
     );
 
@@ -142,6 +238,26 @@ pub async fn insert_subscriber(
     Ok(new_subscriber_id)
 }
 
+#[tracing::instrument(name = "Recording a consent event", skip(executor, clock))]
+pub async fn record_consent_event(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: &Uuid,
+    event: ConsentEvent,
+    clock: &Clock,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO consent_events (id, subscriber_id, event, occurred_at) VALUES ($1, $2, $3, $4)",
+        Uuid::new_v4(),
+        subscriber_id,
+        event.to_string(),
+        clock.now()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct ConfirmationEmailContext<'a> {
     name: &'a str,
@@ -212,7 +328,7 @@ pub enum SendMailError {
     TemplateRenderError(#[from] tera::Error),
 
     #[error(transparent)]
-    HTTPClientError(#[from] reqwest::Error),
+    EmailClientError(#[from] SendEmailError),
 }
 
 impl std::fmt::Debug for SendMailError {
@@ -226,6 +342,9 @@ pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
 
+    #[error(transparent)]
+    RateLimited(#[from] TooManyRequests),
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -242,11 +361,30 @@ impl From<String> for SubscribeError {
     }
 }
 
+impl From<ListError> for SubscribeError {
+    fn from(value: ListError) -> Self {
+        match value {
+            ListError::UnexpectedError(e) => Self::UnexpectedError(e),
+            e => Self::ValidationError(e.to_string()),
+        }
+    }
+}
+
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::RateLimited(e) => e.status_code(),
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::RateLimited(e) => e.error_response(),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
 }
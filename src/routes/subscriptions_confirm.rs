@@ -1,6 +1,13 @@
-use crate::{domain::SubscriberStatus, utils::error_chain_fmt};
+use crate::{
+    clock::Clock,
+    domain::{ConsentEvent, SubscriberStatus},
+    metrics::{record_funnel_step, FunnelStep},
+    routes::{hash_subscription_token, record_consent_event},
+    utils::error_chain_fmt,
+};
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
 use serde::Deserialize;
 use sqlx::PgPool;
@@ -11,19 +18,23 @@ pub struct ConfirmParameters {
     token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(db, params))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(db, params, clock))]
 pub async fn confirm(
     db: web::Data<PgPool>,
     params: web::Query<ConfirmParameters>,
+    clock: web::Data<Clock>,
 ) -> Result<HttpResponse, ConfirmSubscriptionError> {
-    let subscriber_id = get_subscriber_id_from_token(&db, &params.token)
+    let token = get_subscriber_id_from_token(&db, &params.token)
         .await
         .context("Could not get subscriber ID from token")?;
 
-    match subscriber_id {
+    match token {
         // Token does not exist
         None => Err(ConfirmSubscriptionError::SubscriberDoesNotExist),
-        Some(subscriber_id) => {
+        Some(StoredToken {
+            subscriber_id,
+            expires_at,
+        }) => {
             if check_if_subscriber_confirmed(&db, &subscriber_id)
                 .await
                 .context("Could not check if subscriber was confirmed")?
@@ -31,23 +42,36 @@ pub async fn confirm(
                 return Err(ConfirmSubscriptionError::SubscriberAlreadyConfirmedError);
             };
 
-            confirm_subscriber(&db, &subscriber_id)
+            if expires_at <= clock.now() {
+                return Err(ConfirmSubscriptionError::TokenExpired);
+            }
+
+            confirm_subscriber(&db, &subscriber_id, &clock)
                 .await
                 .context("Could not confirm subscriber")?;
+            record_funnel_step(FunnelStep::Confirmed);
             Ok(HttpResponse::Ok().finish())
         }
     }
 }
 
+/// A confirmed subscriber who just joined another list still has that
+/// membership to confirm.
 #[tracing::instrument(name = "Check if subscriber is already confirmed", skip(db))]
 pub async fn check_if_subscriber_confirmed(
     db: &PgPool,
     user_id: &Uuid,
 ) -> Result<bool, sqlx::Error> {
     let subscriber_status = sqlx::query!(
-        "SELECT status FROM subscriptions WHERE id = $1 AND status = $2",
+        r#"
+        SELECT status FROM subscriptions
+        WHERE id = $1 AND status = $2 AND NOT EXISTS (
+            SELECT 1 FROM list_memberships WHERE subscriber_id = $1 AND status = $3
+        )
+        "#,
         user_id,
-        SubscriberStatus::Ok.to_string()
+        SubscriberStatus::Ok.to_string(),
+        SubscriberStatus::PendingConfirmation.to_string()
     )
     .fetch_optional(db)
     .await
@@ -59,31 +83,53 @@ pub async fn check_if_subscriber_confirmed(
     Ok(subscriber_status.is_some())
 }
 
+pub struct StoredToken {
+    pub subscriber_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
 #[tracing::instrument(name = "Getting subscriber ID from token", skip(db, token))]
 pub async fn get_subscriber_id_from_token(
     db: &PgPool,
     token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-                              WHERE subscription_token = $1",
-        token
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        StoredToken,
+        "SELECT subscriber_id, expires_at FROM subscription_tokens \
+                              WHERE subscription_token_hash = $1 AND new_email IS NULL",
+        hash_subscription_token(token)
     )
     .fetch_optional(db)
     .await?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result)
 }
 
-#[tracing::instrument(name = "Confirming user's subscription", skip(db))]
-pub async fn confirm_subscriber(db: &PgPool, user_id: &Uuid) -> Result<(), sqlx::Error> {
+/// Confirms the subscriber along with every list they are waiting to join.
+#[tracing::instrument(name = "Confirming user's subscription", skip(db, clock))]
+pub async fn confirm_subscriber(
+    db: &PgPool,
+    user_id: &Uuid,
+    clock: &Clock,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
     sqlx::query!(
         "UPDATE subscriptions SET status = $2 WHERE id = $1",
         user_id,
         SubscriberStatus::Ok.to_string()
     )
-    .execute(db)
+    .execute(&mut *tx)
     .await?;
+    sqlx::query!(
+        "UPDATE list_memberships SET status = $2 WHERE subscriber_id = $1 AND status = $3",
+        user_id,
+        SubscriberStatus::Ok.to_string(),
+        SubscriberStatus::PendingConfirmation.to_string()
+    )
+    .execute(&mut *tx)
+    .await?;
+    record_consent_event(&mut *tx, user_id, ConsentEvent::Confirmed, clock).await?;
+    tx.commit().await?;
 
     Ok(())
 }
@@ -96,6 +142,9 @@ pub enum ConfirmSubscriptionError {
     #[error("Subscriber does not exist.")]
     SubscriberDoesNotExist,
 
+    #[error("The confirmation link has expired.")]
+    TokenExpired,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -111,6 +160,7 @@ impl ResponseError for ConfirmSubscriptionError {
         match self {
             Self::SubscriberAlreadyConfirmedError => StatusCode::BAD_REQUEST,
             Self::SubscriberDoesNotExist => StatusCode::UNAUTHORIZED,
+            Self::TokenExpired => StatusCode::GONE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,227 @@
+use crate::{
+    clock::Clock,
+    configuration::{ApplicationBaseUrl, HmacSecret},
+    domain::{ConsentEvent, SubscriberStatus},
+    metrics::{record_funnel_step, FunnelStep},
+    routes::record_consent_event,
+    utils::error_chain_fmt,
+};
+use actix_web::{http::header::ContentType, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tera::{Context as TeraContext, Tera};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    tag: String,
+}
+
+impl UnsubscribeParameters {
+    fn verify(&self, secret: &HmacSecret) -> Result<Uuid, UnsubscribeError> {
+        let tag = hex::decode(&self.tag).map_err(|_| UnsubscribeError::InvalidLink)?;
+
+        unsubscribe_mac(secret, &self.subscriber_id)
+            .verify_slice(&tag)
+            .map_err(|_| UnsubscribeError::InvalidLink)?;
+
+        Ok(self.subscriber_id)
+    }
+}
+
+fn unsubscribe_mac(secret: &HmacSecret, subscriber_id: &Uuid) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+/// Builds the signed link a subscriber can use to leave the newsletter.
+///
+/// The link doesn't need to be stored anywhere: the tag can only be produced
+/// with the server's HMAC secret, so it is verified by recomputing it.
+pub fn unsubscribe_link(base_url: &str, secret: &HmacSecret, subscriber_id: &Uuid) -> String {
+    let tag = hex::encode(
+        unsubscribe_mac(secret, subscriber_id)
+            .finalize()
+            .into_bytes(),
+    );
+    format!("{base_url}/unsubscribe?subscriber_id={subscriber_id}&tag={tag}")
+}
+
+#[derive(Serialize)]
+struct UnsubscribePageContext {
+    link: String,
+}
+
+#[tracing::instrument(
+    name = "Show the unsubscribe confirmation page",
+    skip(params, secret, base_url, template)
+)]
+pub async fn unsubscribe_form(
+    params: web::Query<UnsubscribeParameters>,
+    secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    template: web::Data<Tera>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = params.verify(&secret)?;
+
+    let context = UnsubscribePageContext {
+        link: unsubscribe_link(&base_url.0, &secret, &subscriber_id),
+    };
+    let body = template
+        .render(
+            "unsubscribe.html",
+            &TeraContext::from_serialize(&context).unwrap(),
+        )
+        .context("Failed to render the unsubscribe page")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Handles both the form on the confirmation page and RFC 8058 one-click
+/// requests sent by mail clients to the `List-Unsubscribe` URL.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(params, db, secret, template, clock)
+)]
+pub async fn unsubscribe(
+    params: web::Query<UnsubscribeParameters>,
+    db: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    template: web::Data<Tera>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = params.verify(&secret)?;
+
+    if !unsubscribe_subscriber(&db, &subscriber_id, &clock)
+        .await
+        .context("Could not unsubscribe subscriber")?
+    {
+        return Err(UnsubscribeError::SubscriberDoesNotExist);
+    }
+    record_funnel_step(FunnelStep::Unsubscribed);
+
+    let body = template
+        .render("unsubscribed.html", &TeraContext::new())
+        .context("Failed to render the unsubscribed page")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Marking subscriber as unsubscribed", skip(db, clock))]
+pub async fn unsubscribe_subscriber(
+    db: &PgPool,
+    subscriber_id: &Uuid,
+    clock: &Clock,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let result = sqlx::query!(
+        "UPDATE subscriptions SET status = $2 WHERE id = $1",
+        subscriber_id,
+        SubscriberStatus::Unsubscribed.to_string()
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    // The link in an issue leaves every list, not only the one it was sent to
+    sqlx::query!(
+        "UPDATE list_memberships SET status = $2 WHERE subscriber_id = $1",
+        subscriber_id,
+        SubscriberStatus::Unsubscribed.to_string()
+    )
+    .execute(&mut *tx)
+    .await?;
+    record_consent_event(&mut *tx, subscriber_id, ConsentEvent::Unsubscribed, clock).await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLink,
+
+    #[error("Subscriber does not exist.")]
+    SubscriberDoesNotExist,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::SubscriberDoesNotExist => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unsubscribe_link, UnsubscribeParameters};
+    use crate::configuration::HmacSecret;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("secret".to_string()))
+    }
+
+    fn params_from_link(link: &str) -> UnsubscribeParameters {
+        let query = link.split_once('?').unwrap().1;
+        actix_web::web::Query::<UnsubscribeParameters>::from_query(query)
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn a_generated_link_is_valid() {
+        let subscriber_id = Uuid::new_v4();
+        let link = unsubscribe_link("http://localhost", &secret(), &subscriber_id);
+
+        let verified = assert_ok!(params_from_link(&link).verify(&secret()));
+        assert_eq!(verified, subscriber_id);
+    }
+
+    #[test]
+    fn a_link_for_another_subscriber_is_rejected() {
+        let link = unsubscribe_link("http://localhost", &secret(), &Uuid::new_v4());
+        let mut params = params_from_link(&link);
+        params.subscriber_id = Uuid::new_v4();
+
+        assert_err!(params.verify(&secret()));
+    }
+
+    #[test]
+    fn a_link_signed_with_another_secret_is_rejected() {
+        let link = unsubscribe_link(
+            "http://localhost",
+            &HmacSecret(Secret::new("another-secret".to_string())),
+            &Uuid::new_v4(),
+        );
+
+        assert_err!(params_from_link(&link).verify(&secret()));
+    }
+}
@@ -0,0 +1,5 @@
+mod store;
+mod typed;
+
+pub use store::{purge_expired_sessions, PgSessionStore};
+pub use typed::TypedSession;
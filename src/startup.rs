@@ -1,50 +1,229 @@
-use std::net::TcpListener;
+use std::{
+    future::Future,
+    net::TcpListener,
+    time::{Duration, Instant},
+};
 
 use actix_web::dev::Server;
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use tera::Tera;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use crate::{
+    clock::Clock,
     configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
-    run,
+    migrations::run_migrations,
+    run, run_metrics_server,
 };
 
 pub struct Application {
     pub port: u16,
-    pub server: Server,
+    /// Only set when metrics are served on a port of their own.
+    pub metrics_port: Option<u16>,
+    server: Server,
+    metrics_server: Option<Server>,
+    connection_pool: PgPool,
+    grace_period: Duration,
+    background_tasks: TaskTracker,
+    shutdown: CancellationToken,
+    stopped: CancellationToken,
+}
+
+/// Stops a running [`Application`], the same way `SIGTERM` does.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: CancellationToken,
+    stopped: CancellationToken,
+}
+
+impl ShutdownHandle {
+    /// Asks the application to stop, without waiting for it.
+    pub fn trigger(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Asks the application to stop and waits until it has.
+    pub async fn shutdown(&self) {
+        self.trigger();
+        self.stopped.cancelled().await;
+    }
 }
 
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
-        let address = (config.application.host, config.application.port);
-        let connection_pool = PgPoolOptions::new().connect_lazy_with(config.database.with_db());
-
-        let sender_email = config.email.sender().expect("Bad sender email");
-        let timeout = config.email.timeout();
-        let email_client = EmailClient::new(
-            config.email.base_url,
-            sender_email,
-            config.email.token,
-            timeout,
-        );
+        Self::build_with_clock(config, Clock::default()).await
+    }
+
+    /// Same as [`Application::build`], reading the time from `clock`.
+    pub async fn build_with_clock(config: Settings, clock: Clock) -> Result<Self, std::io::Error> {
+        let address = (config.application.host.clone(), config.application.port);
+        let connection_pool = get_connection_pool(&config.database);
+        let email_client = config.email.client();
+        let grace_period = config.application.shutdown_grace_period();
+
+        if config.database.migrate_on_startup {
+            run_migrations(&connection_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
 
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let mut templates = tera::Tera::new("templates/**/*").expect("Could not load templates");
-        templates.autoescape_on(vec![]);
+        let templates = load_templates();
+
+        let (metrics_port, metrics_server) = match config.application.metrics.port {
+            Some(metrics_port) => {
+                let listener = TcpListener::bind((config.application.host.clone(), metrics_port))?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                let server = run_metrics_server(listener, connection_pool.clone(), grace_period)?;
+                (Some(metrics_port), Some(server))
+            }
+            None => (None, None),
+        };
 
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            config.application.base_url,
+            config.application,
             templates,
+            clock,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            metrics_port,
+            server,
+            metrics_server,
+            connection_pool,
+            grace_period,
+            background_tasks: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            stopped: CancellationToken::new(),
+        })
+    }
+
+    pub fn shutdown(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown: self.shutdown.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Runs `task` next to the HTTP server. It is handed a token cancelled on
+    /// shutdown, at which point it should finish what it is doing and return.
+    pub fn spawn_background_task<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        let task = task(self.shutdown.clone());
+        self.background_tasks.spawn(async move {
+            match task.await {
+                Ok(()) => tracing::info!("{} has exited", name),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "{} failed",
+                    name
+                ),
+            }
+        });
+    }
+
+    /// Serves requests until `SIGTERM`, `Ctrl-C` or a [`ShutdownHandle`] asks
+    /// to stop. New connections are then refused, while in-flight requests and
+    /// background tasks get the grace period to complete.
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        let server_handle = self.server.handle();
+        let metrics_server_handle = self.metrics_server.as_ref().map(Server::handle);
+        let mut server = tokio::spawn(self.server);
+        let mut metrics_server = tokio::spawn(async move {
+            match self.metrics_server {
+                Some(server) => server.await,
+                None => std::future::pending().await,
+            }
+        });
+
+        let mut outcome = Ok(());
+        tokio::select! {
+            _ = self.shutdown.cancelled() => tracing::info!("Shutdown requested"),
+            signal = shutdown_signal() => tracing::info!(signal, "Received a shutdown signal"),
+            exited = &mut server => {
+                outcome = flatten(exited);
+                tracing::error!("The API stopped on its own, shutting down");
+            }
+            exited = &mut metrics_server => {
+                outcome = flatten(exited);
+                tracing::error!("The metrics server stopped on its own, shutting down");
+            }
+        }
+
+        let started_at = Instant::now();
+        self.shutdown.cancel();
+        self.background_tasks.close();
+
+        // Waits for in-flight requests, up to the servers' shutdown timeout
+        let stop_metrics_server = async {
+            if let Some(handle) = metrics_server_handle {
+                handle.stop(true).await;
+            }
+        };
+        tokio::join!(server_handle.stop(true), stop_metrics_server);
+
+        let remaining = self.grace_period.saturating_sub(started_at.elapsed());
+        let background_tasks_drained =
+            tokio::time::timeout(remaining, self.background_tasks.wait())
+                .await
+                .is_ok();
+        self.connection_pool.close().await;
+
+        tracing::info!(
+            elapsed_ms = started_at.elapsed().as_millis() as u64,
+            background_tasks_drained,
+            abandoned_background_tasks = self.background_tasks.len(),
+            "Shutdown complete"
+        );
+        self.stopped.cancel();
+
+        outcome
+    }
+}
+
+fn flatten(
+    exited: Result<Result<(), std::io::Error>, tokio::task::JoinError>,
+) -> Result<(), std::io::Error> {
+    exited.map_err(std::io::Error::other)?
+}
+
+async fn shutdown_signal() -> &'static str {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => "SIGINT",
+        _ = terminate => "SIGTERM",
     }
 }
 
 pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(config.with_db())
 }
+
+pub fn load_templates() -> Tera {
+    let mut templates = Tera::new("templates/**/*").expect("Could not load templates");
+    templates.autoescape_on(vec![]);
+    templates
+}
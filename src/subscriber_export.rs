@@ -0,0 +1,304 @@
+use actix_web::web::Bytes;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::{stream::BoxStream, Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::domain::{Email, SubscriberStatus};
+
+/// Chunks buffered ahead of a slow reader before the export pauses.
+const EXPORT_BUFFER: usize = 16;
+
+#[derive(Debug, Serialize)]
+pub struct SubscriberRow {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// Subscribers, most recent first, read from Postgres as they are consumed.
+pub fn subscribers(
+    pool: &PgPool,
+    status: Option<SubscriberStatus>,
+) -> BoxStream<'_, Result<SubscriberRow, sqlx::Error>> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::TEXT IS NULL OR status = $1
+        ORDER BY subscribed_at DESC
+        "#,
+        status.map(|status| status.to_string()),
+    )
+    .fetch(pool)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// A single array of subscribers.
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// The whole list in `format`, in chunks of one subscriber each.
+///
+/// Rows are streamed from Postgres by a task of their own, so a list of any
+/// size is exported without holding it in memory. The task stops when the
+/// returned stream is dropped.
+pub fn export_subscribers(
+    pool: PgPool,
+    status: Option<SubscriberStatus>,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFER);
+    tokio::spawn(async move {
+        if let Err(e) = encode_subscribers(&pool, status, format, &sender).await {
+            tracing::error!(error.cause_chain = ?e, "Failed to export subscribers");
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+async fn encode_subscribers(
+    pool: &PgPool,
+    status: Option<SubscriberStatus>,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut rows = subscribers(pool, status);
+    let mut n_rows = 0;
+
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("Failed to fetch subscribers")?
+    {
+        let chunk = match format {
+            ExportFormat::Csv => {
+                // The header row goes out along with the first subscriber
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(n_rows == 0)
+                    .from_writer(Vec::new());
+                writer.serialize(SubscriberRow {
+                    email: neutralise_formula(row.email),
+                    name: neutralise_formula(row.name),
+                    ..row
+                })?;
+                writer.into_inner()?
+            }
+            ExportFormat::Json => {
+                let mut chunk = if n_rows == 0 { b"[" } else { b"," }.to_vec();
+                serde_json::to_writer(&mut chunk, &row)?;
+                chunk
+            }
+        };
+        n_rows += 1;
+        if sender.send(Ok(chunk.into())).await.is_err() {
+            // Nobody is reading anymore
+            return Ok(());
+        }
+    }
+
+    if format == ExportFormat::Json {
+        let end: &[u8] = if n_rows == 0 { b"[]" } else { b"]" };
+        let _ = sender.send(Ok(Bytes::from_static(end))).await;
+    }
+
+    Ok(())
+}
+
+/// Spreadsheets evaluate cells starting with one of these as formulas.
+const FORMULA_TRIGGERS: [char; 4] = ['=', '+', '-', '@'];
+
+/// Prefixes `cell` with a quote if a spreadsheet opening the export would
+/// otherwise run it as a formula, e.g. a name such as `=HYPERLINK(...)`.
+fn neutralise_formula(cell: String) -> String {
+    if cell.starts_with(FORMULA_TRIGGERS) {
+        format!("'{cell}")
+    } else {
+        cell
+    }
+}
+
+/// Everything held on a subscriber, as handed over on a data access request.
+#[derive(Debug, Serialize)]
+pub struct SubscriberData {
+    pub subscriber: SubscriberDetails,
+    pub confirmation_tokens: Vec<ConfirmationTokenRecord>,
+    pub consent_events: Vec<ConsentEventRecord>,
+    pub list_memberships: Vec<ListMembershipRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriberDetails {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub delivery_frequency: String,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+/// Tokens are only stored hashed, so their value isn't part of the export.
+#[derive(Debug, Serialize)]
+pub struct ConfirmationTokenRecord {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The address the subscriber asked to switch to, for email change tokens.
+    pub new_email: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsentEventRecord {
+    pub event: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListMembershipRecord {
+    pub list: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    /// `delivered`, `failed` or `pending`.
+    pub status: String,
+    /// When it was delivered, given up on or will next be attempted.
+    pub at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Collecting a subscriber's data", skip(pool))]
+pub async fn subscriber_data(
+    pool: &PgPool,
+    email: &Email,
+) -> Result<Option<SubscriberData>, anyhow::Error> {
+    let Some(subscriber) = sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT id, email, name, status, subscribed_at, delivery_frequency, paused_until
+        FROM subscriptions
+        WHERE lower(email) = lower($1)
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscriber")?
+    else {
+        return Ok(None);
+    };
+
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationTokenRecord,
+        r#"
+        SELECT created_at, expires_at, new_email
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the confirmation tokens")?;
+
+    let consent_events = sqlx::query_as!(
+        ConsentEventRecord,
+        r#"
+        SELECT event, occurred_at
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the consent events")?;
+
+    let list_memberships = sqlx::query_as!(
+        ListMembershipRecord,
+        r#"
+        SELECT lists.slug AS list, list_memberships.status, list_memberships.created_at
+        FROM list_memberships
+        JOIN lists USING (list_id)
+        WHERE subscriber_id = $1
+        ORDER BY list_memberships.created_at
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the list memberships")?;
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT
+            newsletter_issue_id AS "newsletter_issue_id!",
+            title AS "title!",
+            status AS "status!",
+            at AS "at!"
+        FROM (
+            SELECT newsletter_issue_id, 'delivered' AS status, delivered_at AS at
+            FROM issue_deliveries
+            WHERE lower(subscriber_email) = lower($1)
+            UNION ALL
+            SELECT newsletter_issue_id, 'failed', failed_at
+            FROM issue_delivery_dead_letters
+            WHERE lower(subscriber_email) = lower($1)
+            UNION ALL
+            SELECT newsletter_issue_id, 'pending', next_attempt_at
+            FROM issue_delivery_queue
+            WHERE lower(subscriber_email) = lower($1)
+        ) AS deliveries
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        ORDER BY at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the deliveries")?;
+
+    Ok(Some(SubscriberData {
+        subscriber,
+        confirmation_tokens,
+        consent_events,
+        list_memberships,
+        deliveries,
+    }))
+}
@@ -0,0 +1,263 @@
+use std::io::Read;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    clock::Clock,
+    configuration::SuppressionSalt,
+    confirmation_email_worker::enqueue_confirmation_email,
+    domain::{ConsentEvent, Email, NewSubscriber, SubscriberName, SubscriberStatus},
+    erasure::is_suppressed,
+    lists::add_membership,
+    metrics::{record_funnel_step, FunnelStep},
+    routes::{does_subscriber_exist, record_consent_event},
+};
+
+/// How imported subscribers are added to the list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportMode {
+    /// They opted in somewhere else already and start receiving issues right away.
+    #[default]
+    Confirmed,
+    /// They are sent the same confirmation email as through `/subscribe`, by
+    /// the background worker once the import is over.
+    SendConfirmation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "reason", rename_all = "snake_case")]
+pub enum LineOutcome {
+    Accepted,
+    /// Imported, waiting for the confirmation email to go out.
+    ConfirmationQueued,
+    AlreadySubscribed,
+    Rejected(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportedLine {
+    /// Line number in the CSV file, the header being line 1.
+    pub line: u64,
+    pub email: String,
+    #[serde(flatten)]
+    pub outcome: LineOutcome,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub lines: Vec<ImportedLine>,
+}
+
+impl ImportReport {
+    fn count(&self, outcome: fn(&LineOutcome) -> bool) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| outcome(&line.outcome))
+            .count()
+    }
+
+    pub fn n_accepted(&self) -> usize {
+        self.count(|outcome| {
+            matches!(
+                outcome,
+                LineOutcome::Accepted | LineOutcome::ConfirmationQueued
+            )
+        })
+    }
+
+    pub fn n_already_subscribed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, LineOutcome::AlreadySubscribed))
+    }
+
+    pub fn n_rejected(&self) -> usize {
+        self.count(|outcome| matches!(outcome, LineOutcome::Rejected(_)))
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportRow {
+    email: String,
+    name: String,
+}
+
+/// Adds the subscribers listed in a CSV file with `email` and `name` columns
+/// to the list `list_id`.
+///
+/// Every row is validated like a `/subscribe` submission, and addresses of
+/// erased subscribers are refused. Invalid rows and addresses already on the
+/// list are reported instead of stopping the import, so a file can be
+/// imported again after fixing the rejected lines. Existing subscribers who
+/// are not on the list yet are added to it.
+#[tracing::instrument(name = "Importing subscribers", skip(pool, input, salt, clock), err)]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    input: impl Read,
+    mode: ImportMode,
+    list_id: Uuid,
+    salt: &SuppressionSalt,
+    clock: &Clock,
+) -> Result<ImportReport, anyhow::Error> {
+    // Rows with too few or too many fields are rejected one by one when
+    // deserialized, rather than by the reader stopping at them
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let headers = reader
+        .headers()
+        .context("Failed to read the CSV header")?
+        .clone();
+    for column in ["email", "name"] {
+        if !headers.iter().any(|header| header == column) {
+            anyhow::bail!("The CSV file has no `{column}` column.");
+        }
+    }
+    let email_index = headers
+        .iter()
+        .position(|header| header == "email")
+        .expect("The header was checked for an email column");
+
+    let mut report = ImportReport::default();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => {
+                return Err(e).context("Failed to read the CSV file");
+            }
+            Err(e) => {
+                report.lines.push(ImportedLine {
+                    line: e.position().map_or(0, |position| position.line()),
+                    email: String::new(),
+                    outcome: LineOutcome::Rejected(e.to_string()),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        let email = record.get(email_index).unwrap_or_default().to_string();
+
+        let subscriber = record
+            .deserialize::<ImportRow>(Some(&headers))
+            .map_err(|e| e.to_string())
+            .and_then(|row| {
+                Ok(NewSubscriber {
+                    email: Email::parse(row.email)?,
+                    name: SubscriberName::parse(row.name)?,
+                })
+            });
+        let outcome = match subscriber {
+            Ok(subscriber) => {
+                import_subscriber(pool, &subscriber, mode, list_id, salt, clock).await?
+            }
+            Err(e) => LineOutcome::Rejected(e),
+        };
+        report.lines.push(ImportedLine {
+            line,
+            email,
+            outcome,
+        });
+    }
+
+    Ok(report)
+}
+
+async fn import_subscriber(
+    pool: &PgPool,
+    subscriber: &NewSubscriber,
+    mode: ImportMode,
+    list_id: Uuid,
+    salt: &SuppressionSalt,
+    clock: &Clock,
+) -> Result<LineOutcome, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to get a connection from Postgres pool")?;
+    if is_suppressed(&mut *tx, salt, &subscriber.email)
+        .await
+        .context("Failed to check the suppression list")?
+    {
+        return Ok(LineOutcome::Rejected(
+            "The subscriber asked for their data to be erased.".into(),
+        ));
+    }
+
+    let status = match mode {
+        ImportMode::Confirmed => SubscriberStatus::Ok,
+        ImportMode::SendConfirmation => SubscriberStatus::PendingConfirmation,
+    };
+    let subscriber_id = match does_subscriber_exist(&mut tx, &subscriber.email)
+        .await
+        .context("Failed to look up the subscriber")?
+    {
+        Some(subscriber_id) => {
+            // Someone who left the list is not brought back by an import
+            let is_member = sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM list_memberships WHERE list_id = $1 AND subscriber_id = $2
+                ) AS "is_member!"
+                "#,
+                list_id,
+                subscriber_id
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to look up the list membership")?;
+            if is_member {
+                return Ok(LineOutcome::AlreadySubscribed);
+            }
+            subscriber_id
+        }
+        None => {
+            let subscriber_id = Uuid::new_v4();
+            // Two imports racing for the same address: the second one finds it taken
+            let query = sqlx::query!(
+                r#"
+                INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (email) DO NOTHING
+                "#,
+                subscriber_id,
+                subscriber.email.as_ref(),
+                subscriber.name.as_ref(),
+                clock.now(),
+                status.to_string(),
+            );
+            let inserted = tx
+                .execute(query)
+                .await
+                .context("Failed to insert subscriber")?
+                .rows_affected();
+            if inserted == 0 {
+                return Ok(LineOutcome::AlreadySubscribed);
+            }
+            subscriber_id
+        }
+    };
+    add_membership(&mut *tx, list_id, subscriber_id, status)
+        .await
+        .context("Failed to add the subscriber to the list")?;
+    record_consent_event(&mut *tx, &subscriber_id, ConsentEvent::Imported, clock)
+        .await
+        .context("Failed to record the import")?;
+
+    if mode == ImportMode::SendConfirmation {
+        enqueue_confirmation_email(&mut *tx, subscriber_id)
+            .await
+            .context("Failed to queue the confirmation email")?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    match mode {
+        ImportMode::Confirmed => Ok(LineOutcome::Accepted),
+        ImportMode::SendConfirmation => {
+            record_funnel_step(FunnelStep::Subscribed);
+            Ok(LineOutcome::ConfirmationQueued)
+        }
+    }
+}
@@ -1,13 +1,33 @@
+use opentelemetry::{
+    global,
+    propagation::Injector,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Config, Sampler, Tracer, TracerProvider},
+    Resource,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_error::ErrorLayer;
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::{OtlpProtocol, TelemetrySettings};
+
+/// Spans are always logged through bunyan; with a `tracer` they are also
+/// turned into OpenTelemetry spans.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Sync + Send + 'static,
@@ -15,14 +35,94 @@ where
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
         .with(ErrorLayer::default())
+        .with(otel_layer)
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set tracing subscriber");
+    // W3C `traceparent`, read from incoming requests and added to outgoing ones
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Builds the provider exporting spans to the configured OTLP collector, or
+/// `None` if the export is disabled. Spans are exported in batches from a
+/// background task, so this must be called from within a Tokio runtime.
+pub fn get_tracer_provider(
+    service_name: String,
+    settings: &TelemetrySettings,
+) -> Result<Option<TracerProvider>, TraceError> {
+    let otlp = &settings.otlp;
+    if !otlp.enabled {
+        return Ok(None);
+    }
+
+    let exporter = match otlp.protocol {
+        OtlpProtocol::Grpc => opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(&otlp.endpoint)
+            .with_timeout(otlp.timeout())
+            .build_span_exporter()?,
+        OtlpProtocol::Http => opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(&otlp.endpoint)
+            .with_timeout(otlp.timeout())
+            .build_span_exporter()?,
+    };
+    let config = Config::default()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sampling_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name)]));
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(config)
+        .build();
+    Ok(Some(provider))
+}
+
+/// Registers the provider globally, which keeps it alive, and returns its tracer.
+pub fn init_tracer_provider(provider: TracerProvider) -> Tracer {
+    let tracer = provider.tracer("zero2prod");
+    global::set_tracer_provider(provider);
+    tracer
+}
+
+/// The trace context of the current span, as headers for an outgoing request.
+pub fn trace_context_headers() -> HeaderMap {
+    let context = tracing::Span::current().context();
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
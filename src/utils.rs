@@ -1,3 +1,11 @@
+use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use serde::Serialize;
+use tera::{Context as TeraContext, Tera};
+
+use crate::csrf::CsrfToken;
+
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
@@ -10,3 +18,59 @@ pub fn error_chain_fmt(
     }
     Ok(())
 }
+
+/// Hides the details of an unexpected error behind a 500 while keeping the
+/// error chain around for the logs.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((actix_web::http::header::LOCATION, location))
+        .finish()
+}
+
+#[derive(Serialize)]
+struct PageContext<'a> {
+    messages: Vec<String>,
+    csrf_token: &'a str,
+}
+
+/// Renders a page holding forms, with the pending flash messages available to
+/// the template as `messages` and the anti-forgery token as `csrf_token`.
+pub fn render_page(
+    template: &Tera,
+    name: &str,
+    flash_messages: &IncomingFlashMessages,
+    csrf_token: &CsrfToken,
+    extra: TeraContext,
+) -> Result<HttpResponse, anyhow::Error> {
+    let page_context = PageContext {
+        messages: flash_messages
+            .iter()
+            .map(|m| m.content().to_string())
+            .collect(),
+        csrf_token: csrf_token.as_ref(),
+    };
+    let mut context = TeraContext::from_serialize(page_context).unwrap();
+    context.extend(extra);
+
+    let body = template
+        .render(name, &context)
+        .with_context(|| format!("Failed to render the {name} page"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
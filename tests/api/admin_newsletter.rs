@@ -0,0 +1,78 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+
+fn newsletter_form(idempotency_key: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app.get_publish_newsletter().await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app
+        .post_publish_newsletter(&newsletter_form(&uuid::Uuid::new_v4().to_string()))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_published_from_the_dashboard_are_delivered_once() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let form = newsletter_form(&uuid::Uuid::new_v4().to_string());
+    let response = app.post_publish_newsletter(&form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    // Submitting the same form again must not publish a second issue
+    let response = app.post_publish_newsletter(&form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    // THEN
+    app.dispatch_all_pending_emails().await;
+}
@@ -0,0 +1,259 @@
+use secrecy::Secret;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    cli::{
+        create_admin, erase, export_subscriber_data, export_subscribers, import_subscribers,
+        list_subscribers, purge_pending, send_test_email,
+    },
+    configuration::SuppressionSalt,
+    domain::{Email, SubscriberStatus},
+    erasure::{is_suppressed, ErasureMode},
+    lists::default_list,
+    subscriber_export::ExportFormat,
+    subscriber_import::ImportMode,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_unconfirmed_subscriber, spawn_app};
+
+fn output(out: Vec<u8>) -> String {
+    String::from_utf8(out).unwrap()
+}
+
+fn suppression_salt() -> SuppressionSalt {
+    SuppressionSalt(Secret::new("salt".into()))
+}
+
+#[tokio::test]
+async fn a_created_admin_can_log_in() {
+    // GIVEN
+    let app = spawn_app().await;
+    let mut out = Vec::new();
+
+    // WHEN
+    create_admin(
+        &app.database,
+        "operator",
+        Secret::new("a-long-enough-password".into()),
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    // THEN
+    assert!(output(out).starts_with("Created operator"));
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "operator",
+            "password": "a-long-enough-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn create_admin_rejects_short_passwords_and_taken_usernames() {
+    // GIVEN
+    let app = spawn_app().await;
+    let mut out = Vec::new();
+
+    // WHEN
+    let short_password = create_admin(
+        &app.database,
+        "operator",
+        Secret::new("short".into()),
+        &mut out,
+    )
+    .await;
+    let taken_username = create_admin(
+        &app.database,
+        &app.test_user.username,
+        Secret::new("a-long-enough-password".into()),
+        &mut out,
+    )
+    .await;
+
+    // THEN
+    assert!(short_password.is_err());
+    assert!(taken_username
+        .unwrap_err()
+        .to_string()
+        .contains("already exists"));
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_confirmed_and_listed() {
+    // GIVEN
+    let app = spawn_app().await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,Ursula\n\
+        not-an-email,Nobody\n\
+        ursula_le_guin@gmail.com,Ursula again\n";
+    let mut out = Vec::new();
+
+    // WHEN
+    import_subscribers(
+        &app.database,
+        csv.as_bytes(),
+        ImportMode::Confirmed,
+        &default_list(),
+        &suppression_salt(),
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    // THEN
+    assert_eq!(
+        output(out),
+        "Line 2: ursula_le_guin@gmail.com imported\n\
+        Line 3: not-an-email rejected, not-an-email is not a valid email\n\
+        Line 4: ursula_le_guin@gmail.com skipped, already subscribed\n\
+        Imported 1 subscriber(s), skipped 1 already subscribed and rejected 1\n"
+    );
+
+    let mut out = Vec::new();
+    list_subscribers(&app.database, Some(SubscriberStatus::Ok), &mut out)
+        .await
+        .unwrap();
+    let listing = output(out);
+    assert!(listing.starts_with("ursula_le_guin@gmail.com\tUrsula\tok\t"));
+    assert!(listing.ends_with("1 subscriber(s)\n"));
+}
+
+#[tokio::test]
+async fn export_writes_subscribers_as_csv() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let mut out = Vec::new();
+
+    // WHEN
+    export_subscribers(&app.database, None, ExportFormat::Csv, &mut out)
+        .await
+        .unwrap();
+
+    // THEN
+    let csv = output(out);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("arsene@lup.in,arsene lupin,pending_confirmation,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn export_can_be_filtered_by_status() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let mut out = Vec::new();
+
+    // WHEN
+    export_subscribers(
+        &app.database,
+        Some(SubscriberStatus::Ok),
+        ExportFormat::Json,
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    // THEN
+    assert_eq!(output(out), "[]");
+}
+
+#[tokio::test]
+async fn the_data_of_a_subscriber_is_printed_as_json() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let email = Email::parse("arsene@lup.in".into()).unwrap();
+    let mut out = Vec::new();
+
+    // WHEN
+    export_subscriber_data(&app.database, &email, &mut out)
+        .await
+        .unwrap();
+
+    // THEN
+    let data: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(data["subscriber"]["status"], "pending_confirmation");
+    assert_eq!(data["consent_events"][0]["event"], "subscribed");
+}
+
+#[tokio::test]
+async fn send_test_email_goes_through_the_email_client() {
+    // GIVEN
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let recipient = Email::parse("operator@example.com".into()).unwrap();
+    let mut out = Vec::new();
+
+    // WHEN
+    send_test_email(&app.email_client, &recipient, &mut out)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(output(out), "Sent a test email to operator@example.com\n");
+}
+
+#[tokio::test]
+async fn purge_pending_reports_the_number_of_purged_subscribers() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 hour'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+    let mut out = Vec::new();
+
+    // WHEN
+    purge_pending(&app.database, &mut out).await.unwrap();
+
+    // THEN
+    assert_eq!(output(out), "Purged 1 pending subscriber(s)\n");
+}
+
+#[tokio::test]
+async fn an_erased_subscriber_is_deleted_and_suppressed() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let email = Email::parse("arsene@lup.in".into()).unwrap();
+    let mut out = Vec::new();
+
+    // WHEN
+    erase(
+        &app.database,
+        &suppression_salt(),
+        &email,
+        ErasureMode::Delete,
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    // THEN
+    assert_eq!(output(out), "Erased arsene@lup.in\n");
+    assert!(is_suppressed(&app.database, &suppression_salt(), &email)
+        .await
+        .unwrap());
+    let audit_event = sqlx::query!("SELECT user_id, action FROM audit_events")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(audit_event.user_id, None);
+    assert_eq!(audit_event.action, "subscriber_deleted");
+}
@@ -0,0 +1,173 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn login_form(app: &crate::helpers::TestApp, csrf_token: &str) -> serde_json::Value {
+    serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+        "csrf_token": csrf_token
+    })
+}
+
+#[tokio::test]
+async fn form_submissions_without_a_csrf_cookie_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscribe", &app.connection_string))
+        .form(&serde_json::json!({
+            "name": "arsene lupin",
+            "email": "arsene@lup.in",
+            "csrf_token": "made-up-token"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn form_submissions_without_a_csrf_token_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // WHEN
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.connection_string))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn form_submissions_with_a_wrong_csrf_token_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+
+    for wrong_token in [csrf_token.replace(|_| true, "0"), "short".to_string()] {
+        // WHEN
+        let response = app
+            .api_client
+            .post(format!("{}/login", &app.connection_string))
+            .form(&login_form(&app, &wrong_token))
+            .send()
+            .await
+            .unwrap();
+
+        // THEN
+        assert_eq!(response.status().as_u16(), 403);
+    }
+}
+
+#[tokio::test]
+async fn the_csrf_token_can_be_sent_as_a_header() {
+    // GIVEN
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+
+    // WHEN
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.connection_string))
+        .header("X-CSRF-Token", &csrf_token)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn cross_site_requests_are_rejected_even_with_a_valid_token() {
+    // GIVEN
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+
+    let cases = [
+        ("Sec-Fetch-Site", "cross-site"),
+        ("Sec-Fetch-Site", "same-site"),
+        ("Origin", "https://evil.example"),
+        ("Origin", "null"),
+    ];
+    for (header, value) in cases {
+        // WHEN
+        let response = app
+            .api_client
+            .post(format!("{}/login", &app.connection_string))
+            .header(header, value)
+            .form(&login_form(&app, &csrf_token))
+            .send()
+            .await
+            .unwrap();
+
+        // THEN
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "The request with {header}: {value} was not rejected"
+        );
+    }
+}
+
+#[tokio::test]
+async fn same_origin_requests_with_a_valid_token_are_accepted() {
+    // GIVEN
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+
+    // WHEN
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.connection_string))
+        .header("Sec-Fetch-Site", "same-origin")
+        .form(&login_form(&app, &csrf_token))
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn file_uploads_without_a_csrf_token_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let form = reqwest::multipart::Form::new()
+        .text("mode", "confirmed")
+        .text("file", "email,name\nursula_le_guin@gmail.com,Ursula\n");
+
+    // WHEN
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/subscribers/import",
+            &app.connection_string
+        ))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,269 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+fn draft_form() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Draft body as plain text",
+        "html_content": "<p>Draft body as HTML</p>"
+    })
+}
+
+/// Creates a draft and returns the path of its page.
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_form("/admin/drafts", &draft_form()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+async fn n_drafts(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM newsletter_drafts"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_work_on_drafts() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let page = app.get_page("/admin/drafts").await;
+    let creation = app.post_form("/admin/drafts", &draft_form()).await;
+
+    // THEN
+    assert_is_redirect_to(&page, "/login");
+    assert_is_redirect_to(&creation, "/login");
+    assert_eq!(n_drafts(&app).await, 0);
+}
+
+#[tokio::test]
+async fn drafts_can_be_saved_and_edited() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    // WHEN
+    let mut form = draft_form();
+    form["title"] = "Edited title".into();
+    let response = app.post_form(&draft_path, &form).await;
+
+    // THEN
+    assert_is_redirect_to(&response, &draft_path);
+    let html_page = app.get_page(&draft_path).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains(r#"value="Edited title""#));
+    let drafts_page = app.get_page("/admin/drafts").await.text().await.unwrap();
+    assert!(drafts_page.contains(&format!(r#"<a href="{draft_path}">Edited title</a>"#)));
+    assert_eq!(n_drafts(&app).await, 1);
+}
+
+#[tokio::test]
+async fn previews_show_the_email_a_subscriber_would_get() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    let expected = app
+        .renderer
+        .render(
+            "<p>Draft body as HTML</p>",
+            "Draft body as plain text",
+            &subscriber_id,
+        )
+        .unwrap();
+
+    // WHEN
+    let html = app
+        .get_page(&format!("{draft_path}/preview?email=arsene%40lup.in"))
+        .await;
+    let text = app
+        .get_page(&format!(
+            "{draft_path}/preview?email=arsene%40lup.in&format=text"
+        ))
+        .await;
+
+    // THEN
+    assert_eq!(html.status().as_u16(), 200);
+    assert_eq!(
+        html.headers().get("Content-Type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    assert_eq!(
+        html.headers().get("Content-Security-Policy").unwrap(),
+        "sandbox"
+    );
+    let html = html.text().await.unwrap();
+    assert_eq!(html, expected.html_content);
+    assert!(html.contains(&expected.unsubscribe_link));
+    assert_eq!(text.status().as_u16(), 200);
+    assert_eq!(text.text().await.unwrap(), expected.text_content);
+}
+
+#[tokio::test]
+async fn previews_find_the_subscriber_whatever_the_case_of_the_address() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    // WHEN
+    let response = app
+        .get_page(&format!("{draft_path}/preview?email=Arsene%40Lup.IN"))
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn previewing_for_someone_who_is_not_a_subscriber_is_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    // WHEN
+    let response = app
+        .get_page(&format!("{draft_path}/preview?email=nobody%40example.com"))
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn test_emails_only_go_to_the_given_address() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app
+        .post_form(
+            &format!("{draft_path}/test"),
+            &serde_json::json!({ "email": "editor@example.com" }),
+        )
+        .await;
+    let html_page = app.get_page(&draft_path).await.text().await.unwrap();
+    let rejected = app
+        .post_form(
+            &format!("{draft_path}/test"),
+            &serde_json::json!({ "email": "arsene@lup.in" }),
+        )
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, &draft_path);
+    assert!(html_page.contains("<p><i>A test email has been sent to editor@example.com.</i></p>"));
+    assert_is_redirect_to(&rejected, &draft_path);
+    let html_page = app.get_page(&draft_path).await.text().await.unwrap();
+    assert!(html_page.contains("arsene@lup.in is not one of the test recipients."));
+    let email_request = mock_guard.received_requests().await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Draft body as HTML</p>"));
+    // Nothing was queued for the subscribers
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn test_emails_to_an_invalid_address_are_not_sent() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app
+        .post_form(
+            &format!("{draft_path}/test"),
+            &serde_json::json!({ "email": "not-an-email" }),
+        )
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, &draft_path);
+    let html_page = app.get_page(&draft_path).await.text().await.unwrap();
+    assert!(html_page.contains("not-an-email is not a valid email"));
+}
+
+#[tokio::test]
+async fn published_drafts_are_delivered_and_removed() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+    let draft_id = draft_path.trim_start_matches("/admin/drafts/");
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let mut form = draft_form();
+    form["idempotency_key"] = uuid::Uuid::new_v4().to_string().into();
+    form["draft_id"] = draft_id.into();
+    let response = app.post_publish_newsletter(&form).await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(n_drafts(&app).await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn deleted_drafts_are_gone() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_path = create_draft(&app).await;
+
+    // WHEN
+    let response = app
+        .post_form(&format!("{draft_path}/delete"), &serde_json::json!({}))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/drafts");
+    assert_eq!(app.get_page(&draft_path).await.status().as_u16(), 404);
+    assert_eq!(n_drafts(&app).await, 0);
+}
@@ -0,0 +1,242 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_and_deliver_newsletter(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_erase_a_subscriber() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app
+        .post_erase_subscriber(&serde_json::json!({"email": "arsene@lup.in"}))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_deleted_subscriber_leaves_no_trace_but_an_audit_event() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+
+    // WHEN
+    let response = app
+        .post_erase_subscriber(&serde_json::json!({"email": "arsene@lup.in"}))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("arsene@lup.in has been erased."));
+
+    for table in [
+        "subscriptions",
+        "subscription_tokens",
+        "consent_events",
+        "issue_deliveries",
+    ] {
+        let n_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+            .fetch_one(&app.database)
+            .await
+            .unwrap();
+        assert_eq!(n_rows, 0, "{table} still has rows");
+    }
+
+    let audit_event = sqlx::query!("SELECT user_id, action FROM audit_events")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(audit_event.user_id, Some(app.test_user.user_id));
+    assert_eq!(audit_event.action, "subscriber_deleted");
+}
+
+#[tokio::test]
+async fn an_anonymized_subscriber_keeps_their_deliveries_without_their_address() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+
+    // WHEN
+    app.post_erase_subscriber(&serde_json::json!({
+        "email": "arsene@lup.in",
+        "mode": "anonymize"
+    }))
+    .await;
+
+    // THEN
+    let subscriber = sqlx::query!("SELECT id, email, name, status FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    let placeholder = format!("{}@erased.invalid", subscriber.id);
+    assert_eq!(subscriber.email, placeholder);
+    assert_eq!(subscriber.name, "");
+    assert_eq!(subscriber.status, "erased");
+
+    let delivery = sqlx::query!("SELECT subscriber_email FROM issue_deliveries")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(delivery.subscriber_email, placeholder);
+
+    let action = sqlx::query_scalar!("SELECT action FROM audit_events")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(action, "subscriber_anonymized");
+}
+
+#[tokio::test]
+async fn every_subscriber_sharing_the_address_in_another_case_is_anonymized() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'Arsene@Lup.IN', 'arsène', now(), 'ok')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.database)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+
+    // WHEN
+    app.post_erase_subscriber(&serde_json::json!({
+        "email": "arsene@lup.in",
+        "mode": "anonymize"
+    }))
+    .await;
+
+    // THEN
+    let subscribers = sqlx::query!("SELECT id, email, status FROM subscriptions")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 2);
+    for subscriber in &subscribers {
+        assert_eq!(
+            subscriber.email,
+            format!("{}@erased.invalid", subscriber.id)
+        );
+        assert_eq!(subscriber.status, "erased");
+    }
+    let mut subjects = sqlx::query_scalar!("SELECT subject_id FROM audit_events")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    let mut ids = subscribers
+        .iter()
+        .map(|subscriber| Some(subscriber.id))
+        .collect::<Vec<_>>();
+    subjects.sort();
+    ids.sort();
+    assert_eq!(subjects, ids);
+    let n_leaked: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT count(*) AS "n!" FROM issue_deliveries
+        WHERE subscriber_email NOT LIKE '%@erased.invalid'
+        "#
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap();
+    assert_eq!(n_leaked, 0);
+}
+
+#[tokio::test]
+async fn an_erased_subscriber_can_not_be_imported_again() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    app.post_erase_subscriber(&serde_json::json!({"email": "arsene@lup.in"}))
+        .await;
+
+    // WHEN
+    let response = app
+        .post_import_subscribers("email,name\nArsene@Lup.in,Arsène\n", "confirmed")
+        .await;
+
+    // THEN
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("The subscriber asked for their data to be erased."));
+    let n_subscribers = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_subscribers, Some(0));
+}
+
+#[tokio::test]
+async fn erasing_an_unknown_address_still_suppresses_it() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    app.post_erase_subscriber(&serde_json::json!({"email": "ursula_le_guin@gmail.com"}))
+        .await;
+
+    // THEN
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("it has been added to the suppression list."));
+    let response = app
+        .post_import_subscribers("email,name\nursula_le_guin@gmail.com,Ursula\n", "confirmed")
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("The subscriber asked for their data to be erased."));
+}
+
+#[tokio::test]
+async fn an_address_is_erased_whatever_its_case_in_the_request() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_and_deliver_newsletter(&app).await;
+
+    // WHEN
+    app.post_erase_subscriber(&serde_json::json!({"email": "Arsene@Lup.IN"}))
+        .await;
+
+    // THEN
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Arsene@Lup.IN has been erased."));
+    for table in ["subscriptions", "issue_deliveries"] {
+        let n_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+            .fetch_one(&app.database)
+            .await
+            .unwrap();
+        assert_eq!(n_rows, 0, "{table} still has rows");
+    }
+}
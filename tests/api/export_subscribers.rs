@@ -0,0 +1,202 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    for path in [
+        "/admin/subscribers/export",
+        "/admin/subscribers/data?email=arsene%40lup.in",
+    ] {
+        // WHEN
+        let response = app.get_page(path).await;
+
+        // THEN
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_csv() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app.get_page("/admin/subscribers/export").await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        r#"attachment; filename="subscribers.csv""#
+    );
+    let csv = response.text().await.unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("arsene@lup.in,arsene lupin,ok,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn cells_a_spreadsheet_would_run_as_formulas_are_exported_as_text() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(r#"UPDATE subscriptions SET name = '=HYPERLINK("http://evil.example","Click")'"#)
+        .execute(&app.database)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app.get_page("/admin/subscribers/export").await;
+
+    // THEN
+    let csv = response.text().await.unwrap();
+    let subscriber = csv.lines().nth(1).unwrap();
+    assert!(subscriber
+        .starts_with(r#"arsene@lup.in,"'=HYPERLINK(""http://evil.example"",""Click"")",ok,"#));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_json_and_filtered_by_status() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let all: serde_json::Value = app
+        .get_page("/admin/subscribers/export?format=json")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let confirmed: serde_json::Value = app
+        .get_page("/admin/subscribers/export?format=json&status=ok")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(all.as_array().unwrap().len(), 1);
+    assert_eq!(all[0]["email"], "arsene@lup.in");
+    assert_eq!(all[0]["status"], "pending_confirmation");
+    assert_eq!(confirmed, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn an_unknown_status_is_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app.get_page("/admin/subscribers/export?status=maybe").await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_subscriber_data_export_holds_their_history() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // WHEN
+    let response = app
+        .get_page("/admin/subscribers/data?email=arsene%40lup.in")
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "arsene@lup.in");
+    assert_eq!(data["subscriber"]["status"], "ok");
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+    let events = data["consent_events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(events, ["subscribed", "confirmed"]);
+    assert_eq!(data["deliveries"][0]["title"], "Newsletter title");
+    assert_eq!(data["deliveries"][0]["status"], "delivered");
+}
+
+#[tokio::test]
+async fn a_subscriber_data_export_is_found_whatever_the_case_of_the_address() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // WHEN
+    let response = app
+        .get_page("/admin/subscribers/data?email=Arsene%40Lup.IN")
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "arsene@lup.in");
+    assert_eq!(data["deliveries"][0]["status"], "delivered");
+}
+
+#[tokio::test]
+async fn exporting_the_data_of_an_unknown_subscriber_returns_404() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app
+        .get_page("/admin/subscribers/data?email=nobody%40example.com")
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 404);
+}
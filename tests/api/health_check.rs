@@ -1,4 +1,19 @@
-use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{header_exists, method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{configuration::Settings, startup::Application};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+fn check<'a>(report: &'a serde_json::Value, name: &str) -> &'a serde_json::Value {
+    report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["name"] == name)
+        .unwrap_or_else(|| panic!("No {name} check in the report"))
+}
 
 #[tokio::test]
 async fn test() {
@@ -12,3 +27,125 @@ async fn test() {
     assert!(result.status().is_success());
     assert_eq!(result.content_length(), Some(0));
 }
+
+#[tokio::test]
+async fn liveness_returns_200() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app.get_liveness().await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_each_check() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app.get_readiness().await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "ok");
+    for name in ["database", "migrations"] {
+        let check = check(&report, name);
+        assert_eq!(check["status"], "ok");
+        assert!(check["latency_ms"].as_f64().unwrap() >= 0.0);
+    }
+    // The email transport is only checked when enabled
+    assert_eq!(report["checks"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn readiness_fails_when_migrations_are_pending() {
+    // GIVEN
+    let app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)"
+    )
+    .execute(&app.database)
+    .await
+    .unwrap();
+
+    // WHEN
+    let response = app.get_readiness().await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 503);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "error");
+    assert_eq!(check(&report, "database")["status"], "ok");
+    let migrations = check(&report, "migrations");
+    assert_eq!(migrations["status"], "error");
+    assert!(migrations["error"]
+        .as_str()
+        .unwrap()
+        .contains("not applied"));
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_database_is_unreachable() {
+    // GIVEN
+    let config = {
+        let mut config = Settings::get().expect("Failed to read configuration");
+        config.application.port = 0;
+        // Nothing listens on the discard port
+        config.database.port = 9;
+        config.application.readiness.timeout_milliseconds = 500;
+        config
+    };
+    let app = Application::build(config).await.unwrap();
+    let address = format!("http://127.0.0.1:{}", app.port);
+    tokio::spawn(app.run_until_stopped());
+
+    // WHEN
+    let response = reqwest::get(format!("{address}/readyz")).await.unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 503);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(check(&report, "database")["status"], "error");
+}
+
+#[tokio::test]
+async fn readiness_checks_the_email_transport_when_enabled() {
+    // GIVEN
+    let app = spawn_app_with(|config| {
+        config.application.readiness.check_email_transport = true;
+    })
+    .await;
+
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .and(header_exists("X-Postmark-Server-Token"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let healthy = app.get_readiness().await;
+    let unhealthy = app.get_readiness().await;
+
+    // THEN
+    assert_eq!(healthy.status().as_u16(), 200);
+    let report: serde_json::Value = healthy.json().await.unwrap();
+    assert_eq!(check(&report, "email")["status"], "ok");
+
+    assert_eq!(unhealthy.status().as_u16(), 503);
+    let report: serde_json::Value = unhealthy.json().await.unwrap();
+    assert_eq!(check(&report, "email")["status"], "error");
+}
@@ -3,20 +3,86 @@ use fake::{
     Fake,
 };
 use once_cell::sync::Lazy;
+use opentelemetry_sdk::trace::TracerProvider;
 
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, Executor, PgPool};
-use wiremock::MockServer;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 use zero2prod::{
-    configuration::{DatabaseSettings, Settings},
-    startup::get_connection_pool,
-    telemetry::{get_subscriber, init_subscriber},
+    authentication::compute_password_hash,
+    clock::Clock,
+    configuration::{
+        DatabaseSettings, EmailTransportKind, HmacSecret, Settings, SubscriptionTokenTtl,
+    },
+    confirmation_email_worker::{try_send_confirmation, ConfirmationMailer},
+    email_client::EmailClient,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    issue_renderer::IssueRenderer,
+    newsletter_scheduler::publish_due_issues,
+    startup::{get_connection_pool, load_templates, ShutdownHandle},
+    telemetry::{get_subscriber, init_subscriber, init_tracer_provider},
 };
 
 pub struct TestApp {
     pub connection_string: String,
     pub port: u16,
+    /// Set when metrics are served on a port of their own.
+    pub metrics_address: Option<String>,
     pub database: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub email_client: EmailClient,
+    pub max_delivery_attempts: u16,
+    pub renderer: IssueRenderer,
+    pub api_client: reqwest::Client,
+    pub shutdown: ShutdownHandle,
+    /// Follows the system clock until a test freezes it.
+    pub clock: Clock,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone()))
+            .expect("Failed to hash password");
+
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user");
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &self.username,
+                "password": &self.password
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
 }
 
 pub fn name() -> String {
@@ -30,17 +96,24 @@ pub fn email() -> String {
 static TRACING: Lazy<()> = Lazy::new(|| {
     let name = "test".to_string();
     let level = "debug".to_string();
+    // Spans get a trace context, to check that it is propagated, but are not exported
+    let tracer = Some(init_tracer_provider(TracerProvider::builder().build()));
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(name, level, std::io::stdout);
+        let subscriber = get_subscriber(name, level, std::io::stdout, tracer);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(name, level, std::io::sink);
+        let subscriber = get_subscriber(name, level, std::io::sink, tracer);
         init_subscriber(subscriber);
     };
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Same as [`spawn_app`], with a chance to tweak the configuration first.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -49,27 +122,54 @@ pub async fn spawn_app() -> TestApp {
         let mut config = Settings::get().expect("Failed to read configuration");
         config.database.database_name = uuid::Uuid::new_v4().to_string();
         config.application.port = 0;
+        config.email.transport = EmailTransportKind::Postmark;
         config.email.base_url = email_server.uri();
+        config.application.test_recipients = vec!["editor@example.com".into()];
+        customize(&mut config);
 
         config
     };
 
     configure_database(&config.database).await;
 
-    let app = zero2prod::startup::Application::build(config.clone())
+    let clock = Clock::default();
+    let app = zero2prod::startup::Application::build_with_clock(config.clone(), clock.clone())
         .await
         .expect("Failed to build app.");
 
     let port = app.port;
 
-    tokio::spawn(app.server);
+    let shutdown = app.shutdown();
+    let metrics_port = app.metrics_port;
+    tokio::spawn(app.run_until_stopped());
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
-    TestApp {
+    let test_app = TestApp {
         database: get_connection_pool(&config.database),
-        connection_string: format!("http://127.0.0.1:{}", app.port),
+        connection_string: format!("http://127.0.0.1:{port}"),
         email_server,
         port,
-    }
+        metrics_address: metrics_port.map(|port| format!("http://127.0.0.1:{port}")),
+        test_user: TestUser::generate(),
+        max_delivery_attempts: config.email.max_attempts,
+        renderer: IssueRenderer::new(
+            load_templates(),
+            config.application.base_url.clone(),
+            HmacSecret(config.application.hmac_secret.clone()),
+        ),
+        email_client: config.email.client(),
+        api_client,
+        shutdown,
+        clock,
+    };
+    test_app.test_user.store(&test_app.database).await;
+
+    test_app
 }
 
 pub struct ConfirmationLinks {
@@ -78,9 +178,73 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.database,
+                &self.email_client,
+                &self.renderer,
+                self.max_delivery_attempts,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Sends the queued confirmation emails of imported subscribers.
+    pub async fn dispatch_all_pending_confirmations(&self) {
+        let templates = load_templates();
+        let mailer = ConfirmationMailer {
+            email_client: &self.email_client,
+            templates: &templates,
+            base_url: &self.connection_string,
+            token_ttl: &SubscriptionTokenTtl(chrono::Duration::hours(24)),
+            clock: &self.clock,
+        };
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_confirmation(&self.database, &mailer, self.max_delivery_attempts)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Runs the scheduler once, as of the test clock's time.
+    pub async fn publish_due_issues(&self) -> u64 {
+        publish_due_issues(&self.database, self.clock.now())
+            .await
+            .unwrap()
+    }
+
+    /// Loads a page holding a form, so that the CSRF cookie is set, and
+    /// returns the token to submit along with the form.
+    pub async fn csrf_token(&self) -> String {
+        let html_page = self.get_page("/subscribe").await.text().await.unwrap();
+        let (_, rest) = html_page
+            .split_once(r#"name="csrf_token" value=""#)
+            .expect("No CSRF token in the page");
+        rest.split('"').next().unwrap().to_string()
+    }
+
+    async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.csrf_token().await.into();
+        body
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        reqwest::Client::new()
-            .post(&format!("{}/subscribe", self.connection_string))
+        let body = format!("{body}&csrf_token={}", self.csrf_token().await);
+        self.api_client
+            .post(format!("{}/subscribe", self.connection_string))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -96,6 +260,29 @@ impl TestApp {
             .expect("Failed to send request")
     }
 
+    pub async fn get_liveness(&self) -> reqwest::Response {
+        self.get_page("/livez").await
+    }
+
+    pub async fn get_readiness(&self) -> reqwest::Response {
+        self.get_page("/readyz").await
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        let address = self
+            .metrics_address
+            .as_ref()
+            .unwrap_or(&self.connection_string);
+        let response = self
+            .api_client
+            .get(format!("{address}/metrics"))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 200);
+        response.text().await.unwrap()
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s: &str| {
@@ -120,27 +307,292 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header in the email");
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    pub fn get_preferences_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let raw_link = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .map(|link| link.as_str().to_string())
+            .find(|link| link.contains("/preferences?"))
+            .expect("No preferences link in the email");
+
+        let mut preferences_link = reqwest::Url::parse(&raw_link).unwrap();
+        assert_eq!(preferences_link.host_str().unwrap(), "127.0.0.1");
+        preferences_link.set_port(Some(self.port)).unwrap();
+        preferences_link
+    }
+
+    pub async fn post_preferences(
+        &self,
+        link: &reqwest::Url,
+        form: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let csrf_token = self.csrf_token().await;
+        let mut form = form.to_vec();
+        form.push(("csrf_token", &csrf_token));
+        self.api_client
+            .post(link.clone())
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.post_newsletters_with_key(body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.connection_string))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_newsletter_schedule(
+        &self,
+        newsletter_issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.connection_string))
+            .put(format!(
+                "{}/newsletters/{newsletter_issue_id}/schedule",
+                &self.connection_string
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn delete_newsletter(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletters/{newsletter_issue_id}",
+                &self.connection_string
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.connection_string))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.get_page("/login").await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.get_page("/admin/dashboard").await
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.get_page("/admin/password").await
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.connection_string))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.get_page("/admin/newsletters").await
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.connection_string))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.get_page("/admin/subscribers/import")
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_import_subscribers(&self, csv: &str, mode: &str) -> reqwest::Response {
+        let form = reqwest::multipart::Form::new()
+            .text("csrf_token", self.csrf_token().await)
+            .text("mode", mode.to_string())
+            .part(
+                "file",
+                reqwest::multipart::Part::text(csv.to_string())
+                    .file_name("subscribers.csv")
+                    .mime_str("text/csv")
+                    .unwrap(),
+            );
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import",
+                &self.connection_string
+            ))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_erase_subscriber<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/erase",
+                &self.connection_string
+            ))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_create_list<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.connection_string))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.connection_string))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Submits an admin form, CSRF token included.
+    pub async fn post_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}", &self.connection_string, path))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_page(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", &self.connection_string, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
-pub async fn configure_database(config: &DatabaseSettings) {
-    // Create database
-    let connection = PgPoolOptions::new()
-        .connect_with(config.without_db())
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=arsene%20lupin&email=arsene%40lup.in";
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
         .await
-        .expect("Failed to connect to Postgres");
+        .error_for_status()
+        .unwrap();
 
-    connection
-        .execute(&*format!(r#"CREATE DATABASE "{}";"#, config.database_name))
+    let links = mock_guard.received_requests().await.pop().unwrap();
+
+    app.get_confirmation_links(&links)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(link.plain_text)
         .await
-        .expect("Failed to create database.");
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub async fn configure_database(config: &DatabaseSettings) {
+    create_database(config).await;
 
     // Migrate database
     let connection_pool = PgPoolOptions::new()
@@ -153,3 +605,26 @@ pub async fn configure_database(config: &DatabaseSettings) {
         .await
         .expect("Failed to migrate the database");
 }
+
+/// Creates an empty database, without running the migrations.
+pub async fn create_database(config: &DatabaseSettings) {
+    let connection = PgPoolOptions::new()
+        .connect_with(config.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+
+    connection
+        .execute(&*format!(r#"CREATE DATABASE "{}";"#, config.database_name))
+        .await
+        .expect("Failed to create database.");
+}
+
+/// The value of a series in a Prometheus text exposition, e.g.
+/// `emails_sent_total` or `http_requests_total{method="GET",route="/livez",status="200"}`.
+pub fn metric_value(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.trim().parse().unwrap())
+}
@@ -0,0 +1,191 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+
+const SUBSCRIBERS_CSV: &str = "email,name\n\
+    ursula_le_guin@gmail.com,Ursula\n\
+    not-an-email,Nobody\n\
+    arsene@lup.in,Arsène\n\
+    octavia_butler@gmail.com,\n";
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = app
+        .post_import_subscribers(SUBSCRIBERS_CSV, "confirmed")
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_confirmed_and_every_line_is_reported() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app
+        .post_import_subscribers(SUBSCRIBERS_CSV, "confirmed")
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page
+        .contains("Imported 1 subscriber(s), skipped 1 already subscribed and rejected 2."));
+    assert!(html_page.contains("not-an-email is not a valid email"));
+    assert!(html_page.contains("Already subscribed"));
+
+    let statuses = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.database)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.email, row.status))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            ("arsene@lup.in".to_string(), "ok".to_string()),
+            ("ursula_le_guin@gmail.com".to_string(), "ok".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_imported_with_confirmation_are_sent_a_confirmation_email() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app
+        .post_import_subscribers(
+            "email,name\nursula_le_guin@gmail.com,Ursula\n",
+            "send-confirmation",
+        )
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported, confirmation email queued"));
+    // Sent by the worker, not while the upload is being answered
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    let subscriber = sqlx::query!("SELECT email, status FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.email, "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber.status, "pending_confirmation");
+
+    app.dispatch_all_pending_confirmations().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn confirmation_emails_that_fail_are_kept_for_a_retry() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    app.post_import_subscribers(
+        "email,name\nursula_le_guin@gmail.com,Ursula\n",
+        "send-confirmation",
+    )
+    .await;
+
+    // WHEN
+    app.dispatch_all_pending_confirmations().await;
+
+    // THEN
+    let n_retries = sqlx::query_scalar!("SELECT n_retries FROM confirmation_email_queue")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_retries, 1);
+    // No token is left behind for the email that did not go out
+    let n_tokens = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM subscription_tokens"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn a_file_without_the_expected_columns_is_not_imported() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app
+        .post_import_subscribers("address\nursula_le_guin@gmail.com\n", "confirmed")
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html_page = app.get_import_subscribers_html().await;
+    assert!(html_page.contains("The CSV file has no `email` column."));
+}
+
+#[tokio::test]
+async fn a_malformed_line_is_rejected_without_stopping_the_import() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,Ursula\n\
+        missing_name@gmail.com\n\
+        octavia_butler@gmail.com,Octavia\n";
+
+    // WHEN
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page
+        .contains("Imported 2 subscriber(s), skipped 0 already subscribed and rejected 1."));
+    assert!(html_page.contains("expected field, but got end of row"));
+    let emails = sqlx::query_scalar!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(
+        emails,
+        vec!["octavia_butler@gmail.com", "ursula_le_guin@gmail.com"]
+    );
+}
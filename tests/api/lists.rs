@@ -0,0 +1,475 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    cli::{create_list, import_subscribers, print_lists},
+    configuration::SuppressionSalt,
+    domain::ListSlug,
+    lists,
+    subscriber_import::ImportMode,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, ConfirmationLinks, TestApp,
+};
+
+fn slug(slug: &str) -> ListSlug {
+    ListSlug::parse(slug.to_string()).unwrap()
+}
+
+async fn create_blog_list(app: &TestApp) {
+    lists::create_list(&app.database, &slug("blog"), "Engineering blog")
+        .await
+        .unwrap();
+}
+
+/// Subscribes `email` to `list` and returns the link sent to confirm it.
+async fn subscribe_to_list(app: &TestApp, email: &str, list: &str) -> ConfirmationLinks {
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    // Otherwise joining a second list right away runs into the resend cooldown
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+    app.post_subscriptions(format!("name=le%20guin&email={email}&list={list}"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let request = mock_guard.received_requests().await.pop().unwrap();
+    app.get_confirmation_links(&request)
+}
+
+async fn confirm(links: ConfirmationLinks) {
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn membership_statuses(app: &TestApp, email: &str) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships
+        JOIN lists USING (list_id)
+        JOIN subscriptions ON subscriptions.id = list_memberships.subscriber_id
+        WHERE subscriptions.email = $1
+        ORDER BY lists.slug
+        "#,
+        email
+    )
+    .fetch_all(&app.database)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.slug, row.status))
+    .collect()
+}
+
+fn membership(list: &str, status: &str) -> (String, String) {
+    (list.to_string(), status.to_string())
+}
+
+async fn publish_to(app: &TestApp, lists: &[&str]) -> reqwest::Response {
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        },
+        "lists": lists
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn subscribing_without_a_list_joins_the_default_list() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // THEN
+    assert_eq!(
+        membership_statuses(&app, "ursula_le_guin@gmail.com").await,
+        vec![membership("newsletter", "pending_confirmation")]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_to_a_list_that_does_not_exist_is_rejected_with_400() {
+    // GIVEN
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("unknown", "a list that does not exist"),
+        ("Not%20a%20slug", "an invalid list name"),
+    ];
+
+    for (list, description) in test_cases {
+        // WHEN
+        let response = app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com&list={list}"
+            ))
+            .await;
+
+        // THEN
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {description}."
+        );
+    }
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+}
+
+#[tokio::test]
+async fn the_subscription_form_offers_every_list() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+
+    // WHEN
+    let html_page = app.get_page("/subscribe").await.text().await.unwrap();
+
+    // THEN
+    assert!(html_page.contains(r#"<option value="blog">Engineering blog</option>"#));
+    assert!(html_page.contains(r#"<option value="newsletter" selected>Newsletter</option>"#));
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_has_to_confirm_each_new_list() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+
+    // WHEN
+    let links = subscribe_to_list(&app, "arsene%40lup.in", "blog").await;
+
+    // THEN
+    assert_eq!(
+        membership_statuses(&app, "arsene@lup.in").await,
+        vec![
+            membership("blog", "pending_confirmation"),
+            membership("newsletter", "ok"),
+        ]
+    );
+    confirm(links).await;
+    assert_eq!(
+        membership_statuses(&app, "arsene@lup.in").await,
+        vec![membership("blog", "ok"), membership("newsletter", "ok")]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_lists_they_are_published_to() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+    confirm(subscribe_to_list(&app, "ursula_le_guin%40gmail.com", "blog").await).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = publish_to(&app, &["blog"]).await;
+    app.dispatch_all_pending_emails().await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 202);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn a_pending_list_membership_receives_no_issue() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+    subscribe_to_list(&app, "arsene%40lup.in", "blog").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    publish_to(&app, &["blog"])
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // THEN the mock verifies on drop that nothing was sent
+}
+
+#[tokio::test]
+async fn a_subscriber_on_several_of_the_lists_receives_the_issue_once() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "blog").await).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    publish_to(&app, &["blog", "newsletter"])
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // THEN the mock verifies on drop that a single email was sent
+}
+
+#[tokio::test]
+async fn publishing_to_a_list_that_does_not_exist_is_rejected_with_400() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = publish_to(&app, &["newsletter", "unknown"]).await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 400);
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn unsubscribing_leaves_every_list() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "blog").await).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_to(&app, &["blog"])
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(requests.last().unwrap());
+
+    // WHEN
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // THEN
+    assert_eq!(
+        membership_statuses(&app, "arsene@lup.in").await,
+        vec![
+            membership("blog", "unsubscribed"),
+            membership("newsletter", "unsubscribed"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let page = app.get_page("/admin/lists").await;
+    let creation = app
+        .post_create_list(&serde_json::json!({"slug": "blog", "name": "Engineering blog"}))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&page, "/login");
+    assert_is_redirect_to(&creation, "/login");
+}
+
+#[tokio::test]
+async fn lists_can_be_created_from_the_admin_dashboard() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // WHEN
+    let response = app
+        .post_create_list(&serde_json::json!({"slug": "blog", "name": "Engineering blog"}))
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_page("/admin/lists").await.text().await.unwrap();
+    assert!(html_page.contains("The list blog has been created."));
+    assert!(html_page.contains("<td>Engineering blog</td>"));
+}
+
+#[tokio::test]
+async fn creating_a_list_with_a_taken_or_invalid_slug_is_refused() {
+    // GIVEN
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        ("newsletter", "There is already a list called newsletter."),
+        (
+            "Engineering blog",
+            "Engineering blog is not a valid list name",
+        ),
+    ];
+
+    for (slug, message) in test_cases {
+        // WHEN
+        let response = app
+            .post_create_list(&serde_json::json!({"slug": slug, "name": "Engineering blog"}))
+            .await;
+
+        // THEN
+        assert_is_redirect_to(&response, "/admin/lists");
+        let html_page = app.get_page("/admin/lists").await.text().await.unwrap();
+        assert!(html_page.contains(message), "No error for {slug}");
+    }
+}
+
+#[tokio::test]
+async fn issues_published_from_the_dashboard_go_to_the_checked_lists() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    confirm(subscribe_to_list(&app, "arsene%40lup.in", "newsletter").await).await;
+    confirm(subscribe_to_list(&app, "ursula_le_guin%40gmail.com", "blog").await).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(r#"name="lists" value="newsletter" checked"#));
+
+    // WHEN
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let csrf_token = app.csrf_token().await;
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.connection_string))
+        .form(&[
+            ("title", "Newsletter title"),
+            ("text_content", "Newsletter body as plain text"),
+            ("html_content", "<p>Newsletter body as HTML</p>"),
+            ("idempotency_key", &idempotency_key),
+            ("lists", "newsletter"),
+            ("lists", "blog"),
+            ("csrf_token", &csrf_token),
+        ])
+        .send()
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn importing_an_existing_subscriber_into_another_list_adds_them_to_it() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_blog_list(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let salt = SuppressionSalt(secrecy::Secret::new("salt".to_string()));
+    let mut out = Vec::new();
+
+    // WHEN
+    import_subscribers(
+        &app.database,
+        "email,name\narsene@lup.in,Arsène\n".as_bytes(),
+        ImportMode::Confirmed,
+        &slug("blog"),
+        &salt,
+        &mut out,
+    )
+    .await
+    .unwrap();
+
+    // THEN
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .starts_with("Line 2: arsene@lup.in imported\n"));
+    assert_eq!(
+        membership_statuses(&app, "arsene@lup.in").await,
+        vec![membership("blog", "ok"), membership("newsletter", "ok")]
+    );
+}
+
+#[tokio::test]
+async fn lists_can_be_created_and_listed_from_the_command_line() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut out = Vec::new();
+
+    // WHEN
+    create_list(&app.database, &slug("blog"), "Engineering blog", &mut out)
+        .await
+        .unwrap();
+    print_lists(&app.database, &mut out).await.unwrap();
+
+    // THEN
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("Created blog ("));
+    assert!(out.ends_with(
+        "blog\tEngineering blog\t0 subscriber(s)\n\
+        newsletter\tNewsletter\t1 subscriber(s)\n"
+    ));
+}
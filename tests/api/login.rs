@@ -0,0 +1,60 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // The flash message is only shown once
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+
+    // THEN
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn sessions_are_stored_server_side() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    app.test_user.login(&app).await;
+
+    // THEN
+    let n_sessions = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM sessions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_sessions, 1);
+}
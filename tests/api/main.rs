@@ -1,5 +1,23 @@
+mod admin_dashboard;
+mod admin_newsletter;
+mod change_password;
+mod cli;
+mod csrf;
+mod drafts;
+mod erase_subscribers;
+mod export_subscribers;
 mod health_check;
 mod helpers;
+mod import_subscribers;
+mod lists;
+mod login;
+mod metrics;
+mod migrations;
 mod newsletter;
+mod preferences;
+mod scheduled_newsletters;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod telemetry;
+mod unsubscribe;
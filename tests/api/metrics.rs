@@ -0,0 +1,111 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{metric_value, spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn requests_are_counted_by_route_pattern() {
+    // GIVEN
+    let app = spawn_app().await;
+    let series = r#"http_requests_total{method="GET",route="/subscribe/confirm",status="400"}"#;
+    let before = metric_value(&app.get_metrics().await, series).unwrap_or(0.0);
+
+    // WHEN
+    app.get_page("/subscribe/confirm").await;
+
+    // THEN
+    let metrics = app.get_metrics().await;
+    assert!(metric_value(&metrics, series).unwrap() >= before + 1.0);
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/subscribe/confirm"}"#
+    ));
+}
+
+#[tokio::test]
+async fn unknown_paths_share_a_single_label() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    app.get_page("/wp-admin/install.php").await;
+
+    // THEN
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains(r#"route="unmatched""#));
+    assert!(!metrics.contains("/wp-admin"));
+}
+
+#[tokio::test]
+async fn database_pool_gauges_are_exposed() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let metrics = app.get_metrics().await;
+
+    // THEN
+    assert!(metric_value(&metrics, r#"db_pool_connections{state="idle"}"#).is_some());
+    assert!(metric_value(&metrics, r#"db_pool_connections{state="active"}"#).is_some());
+}
+
+#[tokio::test]
+async fn subscribing_updates_the_funnel_and_email_counters() {
+    // GIVEN
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let subscribed = r#"subscription_funnel_total{step="subscribed"}"#;
+    let metrics = app.get_metrics().await;
+    let subscribed_before = metric_value(&metrics, subscribed).unwrap();
+    let sent_before = metric_value(&metrics, "emails_sent_total").unwrap();
+
+    // WHEN
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let metrics = app.get_metrics().await;
+    assert!(metric_value(&metrics, subscribed).unwrap() >= subscribed_before + 1.0);
+    assert!(metric_value(&metrics, "emails_sent_total").unwrap() >= sent_before + 1.0);
+}
+
+#[tokio::test]
+async fn failed_emails_are_counted() {
+    // GIVEN
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    let failed_before = metric_value(&app.get_metrics().await, "emails_failed_total").unwrap();
+
+    // WHEN
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // THEN
+    let metrics = app.get_metrics().await;
+    assert!(metric_value(&metrics, "emails_failed_total").unwrap() >= failed_before + 1.0);
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_separate_port() {
+    // GIVEN
+    let app = spawn_app_with(|config| config.application.metrics.port = Some(0)).await;
+
+    // WHEN
+    let metrics = app.get_metrics().await;
+    let on_main_port = app.get_page("/metrics").await;
+
+    // THEN
+    assert!(metrics.contains("http_requests_total"));
+    assert_eq!(on_main_port.status().as_u16(), 404);
+}
@@ -0,0 +1,114 @@
+use sqlx::PgPool;
+use zero2prod::{
+    configuration::Settings,
+    migrations::{migration_status, pending_migrations, run_migrations, MigrationState, MIGRATOR},
+    startup::{get_connection_pool, Application},
+};
+
+use crate::helpers::create_database;
+
+/// Settings pointing to a new, empty database.
+async fn empty_database() -> (Settings, PgPool) {
+    let mut config = Settings::get().expect("Failed to read configuration");
+    config.database.database_name = uuid::Uuid::new_v4().to_string();
+    config.application.port = 0;
+    create_database(&config.database).await;
+    let pool = get_connection_pool(&config.database);
+
+    (config, pool)
+}
+
+fn n_migrations() -> usize {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .count()
+}
+
+#[tokio::test]
+async fn migrations_are_not_run_on_startup_by_default() {
+    // GIVEN
+    let (config, pool) = empty_database().await;
+
+    // WHEN
+    Application::build(config).await.unwrap();
+
+    // THEN
+    assert_eq!(
+        pending_migrations(&pool).await.unwrap().len(),
+        n_migrations()
+    );
+}
+
+#[tokio::test]
+async fn migrations_are_run_on_startup_when_enabled() {
+    // GIVEN
+    let (mut config, pool) = empty_database().await;
+    config.database.migrate_on_startup = true;
+
+    // WHEN
+    Application::build(config).await.unwrap();
+
+    // THEN
+    assert!(pending_migrations(&pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn replicas_starting_together_do_not_race() {
+    // GIVEN
+    let (mut config, pool) = empty_database().await;
+    config.database.migrate_on_startup = true;
+
+    // WHEN
+    let (first, second, third) = tokio::join!(
+        Application::build(config.clone()),
+        Application::build(config.clone()),
+        Application::build(config),
+    );
+
+    // THEN
+    assert!(first.is_ok() && second.is_ok() && third.is_ok());
+    let n_applied: i64 = sqlx::query_scalar("SELECT count(*) FROM _sqlx_migrations")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(n_applied as usize, n_migrations());
+}
+
+#[tokio::test]
+async fn status_reports_pending_then_applied_migrations() {
+    // GIVEN
+    let (_, pool) = empty_database().await;
+    let before = migration_status(&pool).await.unwrap();
+
+    // WHEN
+    run_migrations(&pool).await.unwrap();
+
+    // THEN
+    let after = migration_status(&pool).await.unwrap();
+    assert_eq!(before.len(), n_migrations());
+    assert!(before.iter().all(|m| m.state == MigrationState::Pending));
+    assert!(after.iter().all(|m| m.state == MigrationState::Applied));
+}
+
+#[tokio::test]
+async fn status_reports_migrations_modified_since_they_were_applied() {
+    // GIVEN
+    let (_, pool) = empty_database().await;
+    run_migrations(&pool).await.unwrap();
+    let first = MIGRATOR.iter().next().unwrap().version;
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = $1")
+        .bind(first)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // WHEN
+    let status = migration_status(&pool).await.unwrap();
+
+    // THEN
+    let modified = status.iter().find(|m| m.version == first).unwrap();
+    assert_eq!(modified.state, MigrationState::Modified);
+    // The migrator refuses to go on until the file is restored
+    assert!(run_migrations(&pool).await.is_err());
+}
@@ -3,7 +3,10 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use zero2prod::issue_delivery_worker::requeue_dead_letters;
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -28,10 +31,11 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
 
     let response = app.post_newsletters(newsletter_json).await;
+    app.dispatch_all_pending_emails().await;
 
     // THEN
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
 }
 
 #[tokio::test]
@@ -59,9 +63,10 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
 
     let response = app.post_newsletters(newsletter_json).await;
+    app.dispatch_all_pending_emails().await;
 
     // THEN
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
 }
 
 #[tokio::test]
@@ -100,30 +105,325 @@ async fn newsletters_returns_400_for_malformed_body() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=arsene%20lupin&email=arsene%40lup.in";
-    let mock_guard = Mock::given(path("/email"))
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.connection_string))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    let username = uuid::Uuid::new_v4().to_string();
+    let password = uuid::Uuid::new_v4().to_string();
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.connection_string))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = uuid::Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.connection_string))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+}
+
+#[tokio::test]
+async fn requests_missing_an_idempotency_key_are_rejected() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.connection_string))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // WHEN
+    let first_response = app
+        .post_newsletters_with_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    let second_response = app
+        .post_newsletters_with_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // THEN
+    assert_eq!(first_response.status().as_u16(), 202);
+    assert_eq!(second_response.status().as_u16(), 202);
+    assert_eq!(
+        first_response.text().await.unwrap(),
+        second_response.text().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // WHEN
+    let first_response = app.post_newsletters_with_key(newsletter_request_body(), &idempotency_key);
+    let second_response =
+        app.post_newsletters_with_key(newsletter_request_body(), &idempotency_key);
+    let (first_response, second_response) = tokio::join!(first_response, second_response);
+    app.dispatch_all_pending_emails().await;
+
+    // THEN
+    assert_eq!(first_response.status(), second_response.status());
+    assert_eq!(
+        first_response.text().await.unwrap(),
+        second_response.text().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn newsletter_deliveries_are_enqueued_instead_of_sent_inline() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    let pending = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.database)
+        .await
+        .expect("Failed to fetch delivery queue");
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].subscriber_email, "arsene@lup.in");
+
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn make_pending_deliveries_due(app: &TestApp) {
+    sqlx::query!("UPDATE issue_delivery_queue SET next_attempt_at = now()")
+        .execute(&app.database)
+        .await
+        .expect("Failed to reschedule pending deliveries");
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
-    app.post_subscriptions(body.into())
+
+    app.post_newsletters(newsletter_request_body())
         .await
         .error_for_status()
         .unwrap();
 
-    let links = mock_guard.received_requests().await.pop().unwrap();
+    // WHEN
+    app.dispatch_all_pending_emails().await;
+    let pending = sqlx::query!("SELECT n_retries, next_attempt_at FROM issue_delivery_queue")
+        .fetch_one(&app.database)
+        .await
+        .expect("The failed delivery was not kept in the queue");
+
+    // THEN
+    assert_eq!(pending.n_retries, 1);
+    assert!(pending.next_attempt_at > chrono::Utc::now());
+
+    make_pending_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
 
-    app.get_confirmation_links(&links)
+    let pending = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(link.plain_text)
+#[tokio::test]
+async fn deliveries_exhausting_their_attempts_are_dead_lettered() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.max_delivery_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
         .await
-        .unwrap()
         .error_for_status()
         .unwrap();
+
+    // WHEN
+    for _ in 0..app.max_delivery_attempts {
+        make_pending_deliveries_due(&app).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // THEN
+    let pending = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
+
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, n_attempts FROM issue_delivery_dead_letters")
+            .fetch_one(&app.database)
+            .await
+            .expect("The delivery was not dead lettered");
+    assert_eq!(dead_letter.subscriber_email, "arsene@lup.in");
+    assert_eq!(dead_letter.n_attempts as u16, app.max_delivery_attempts);
+}
+
+#[tokio::test]
+async fn dead_lettered_deliveries_can_be_requeued() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(app.max_delivery_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    for _ in 0..app.max_delivery_attempts {
+        make_pending_deliveries_due(&app).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // WHEN
+    let n_requeued = requeue_dead_letters(&app.database, None).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // THEN
+    assert_eq!(n_requeued, 1);
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert!(dead_letters.is_empty());
 }
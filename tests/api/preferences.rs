@@ -0,0 +1,625 @@
+use chrono::DurationRound;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+/// Publishes an issue and returns the emails sent for it.
+async fn publish_and_dispatch(app: &TestApp, n_expected: u64) -> Vec<wiremock::Request> {
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(n_expected)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    mock_guard.received_requests().await
+}
+
+async fn preferences_link(app: &TestApp) -> reqwest::Url {
+    let requests = publish_and_dispatch(app, 1).await;
+    app.get_preferences_link(&requests[0])
+}
+
+fn redirect_target(link: &reqwest::Url) -> String {
+    format!("/preferences?{}", link.query().unwrap())
+}
+
+fn preferences_form<'a>(name: &'a str, email: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("name", name),
+        ("email", email),
+        ("lists", "newsletter"),
+        ("frequency", "immediate"),
+        ("pause_weeks", ""),
+    ]
+}
+
+struct StoredPreferences {
+    name: String,
+    email: String,
+    delivery_frequency: String,
+    paused_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+async fn stored_preferences(app: &TestApp) -> StoredPreferences {
+    sqlx::query_as!(
+        StoredPreferences,
+        "SELECT name, email, delivery_frequency, paused_until FROM subscriptions"
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_include_a_link_to_the_preferences_page() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+
+    // WHEN
+    let response = app.api_client.get(link).send().await.unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"name="name" value="arsene lupin""#));
+    assert!(html_page.contains(r#"name="email" value="arsene@lup.in""#));
+    assert!(html_page.contains(r#"name="lists" value="newsletter" checked"#));
+}
+
+#[tokio::test]
+async fn tampered_preferences_links_are_rejected_with_401() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = preferences_link(&app).await;
+    let tampered_query = link.query().unwrap().replace("tag=", "tag=00");
+    link.set_query(Some(&tampered_query));
+
+    // WHEN
+    let page = app.api_client.get(link.clone()).send().await.unwrap();
+    let update = app
+        .post_preferences(&link, &preferences_form("Mallory", "arsene@lup.in"))
+        .await;
+
+    // THEN
+    assert_eq!(page.status().as_u16(), 401);
+    assert_eq!(update.status().as_u16(), 401);
+    assert_eq!(stored_preferences(&app).await.name, "arsene lupin");
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name_and_delivery_frequency() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let mut form = preferences_form("Arsène Lupin", "arsene@lup.in");
+    form[3] = ("frequency", "weekly");
+
+    // WHEN
+    let response = app.post_preferences(&link, &form).await;
+
+    // THEN
+    assert_is_redirect_to(&response, &redirect_target(&link));
+    let html_page = app.api_client.get(link).send().await.unwrap();
+    assert!(html_page
+        .text()
+        .await
+        .unwrap()
+        .contains("Your preferences have been saved."));
+    let stored = stored_preferences(&app).await;
+    assert_eq!(stored.name, "Arsène Lupin");
+    assert_eq!(stored.delivery_frequency, "weekly");
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_refused() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let test_cases = vec![
+        (
+            ("name", "{Arsène}"),
+            "{Arsène} is not a valid subscriber name",
+        ),
+        (
+            ("email", "not-an-email"),
+            "not-an-email is not a valid email",
+        ),
+        (
+            ("pause_weeks", "53"),
+            "Deliveries can be paused for up to 52 weeks.",
+        ),
+    ];
+
+    for ((field, value), message) in test_cases {
+        let mut form = preferences_form("arsene lupin", "arsene@lup.in");
+        form.retain(|(name, _)| *name != field);
+        form.push((field, value));
+
+        // WHEN
+        let response = app.post_preferences(&link, &form).await;
+
+        // THEN
+        assert_is_redirect_to(&response, &redirect_target(&link));
+        let html_page = app.api_client.get(link.clone()).send().await.unwrap();
+        assert!(
+            html_page.text().await.unwrap().contains(message),
+            "No error message for {field}={value}"
+        );
+    }
+    let stored = stored_preferences(&app).await;
+    assert_eq!(stored.name, "arsene lupin");
+    assert!(stored.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn subscribers_can_switch_lists() {
+    // GIVEN
+    let app = spawn_app().await;
+    zero2prod::lists::create_list(
+        &app.database,
+        &zero2prod::domain::ListSlug::parse("blog".into()).unwrap(),
+        "Engineering blog",
+    )
+    .await
+    .unwrap();
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let mut form = preferences_form("arsene lupin", "arsene@lup.in");
+    form[2] = ("lists", "blog");
+
+    // WHEN
+    app.post_preferences(&link, &form).await;
+
+    // THEN
+    let memberships = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships
+        JOIN lists USING (list_id)
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.database)
+    .await
+    .unwrap();
+    let memberships: Vec<_> = memberships
+        .iter()
+        .map(|row| (row.slug.as_str(), row.status.as_str()))
+        .collect();
+    assert_eq!(
+        memberships,
+        vec![("blog", "ok"), ("newsletter", "unsubscribed")]
+    );
+    publish_and_dispatch(&app, 0).await;
+}
+
+#[tokio::test]
+async fn paused_subscribers_receive_no_issue_until_the_pause_is_over() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let now = chrono::Utc::now()
+        .duration_trunc(chrono::Duration::seconds(1))
+        .unwrap();
+    app.clock.freeze_at(now);
+    let mut form = preferences_form("arsene lupin", "arsene@lup.in");
+    form[4] = ("pause_weeks", "2");
+
+    // WHEN
+    app.post_preferences(&link, &form).await;
+
+    // THEN
+    let paused_until = stored_preferences(&app).await.paused_until.unwrap();
+    assert_eq!(paused_until, now + chrono::Duration::weeks(2));
+    publish_and_dispatch(&app, 0).await;
+
+    sqlx::query!(
+        "UPDATE subscriptions SET paused_until = $1",
+        now - chrono::Duration::minutes(1)
+    )
+    .execute(&app.database)
+    .await
+    .unwrap();
+    publish_and_dispatch(&app, 1).await;
+}
+
+#[tokio::test]
+async fn pausing_for_0_weeks_resumes_deliveries() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let mut form = preferences_form("arsene lupin", "arsene@lup.in");
+    form[4] = ("pause_weeks", "2");
+    app.post_preferences(&link, &form).await;
+
+    // WHEN
+    form[4] = ("pause_weeks", "0");
+    app.post_preferences(&link, &form).await;
+
+    // THEN
+    assert!(stored_preferences(&app).await.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn digest_subscribers_receive_the_issues_of_the_period_in_one_email() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let mut form = preferences_form("arsene lupin", "arsene@lup.in");
+    form[3] = ("frequency", "daily");
+    app.post_preferences(&link, &form).await;
+
+    // WHEN
+    publish_and_dispatch(&app, 0).await;
+    publish_and_dispatch(&app, 0).await;
+    sqlx::query!("UPDATE issue_delivery_queue SET next_attempt_at = now()")
+        .execute(&app.database)
+        .await
+        .unwrap();
+
+    // THEN
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let request = mock_guard.received_requests().await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["Subject"], "Your daily digest: 2 new issues");
+    let n_delivered = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM issue_deliveries"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    // The issue sent to get the link, plus the two in the digest
+    assert_eq!(n_delivered, 3);
+}
+
+/// Asks to move the subscriber to `new_email`, and returns the emails sent
+/// to the new and the old address.
+async fn request_email_change(
+    app: &TestApp,
+    link: &reqwest::Url,
+    new_email: &str,
+) -> (wiremock::Request, wiremock::Request) {
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_preferences(link, &preferences_form("arsene lupin", new_email))
+        .await;
+    assert_is_redirect_to(&response, &redirect_target(link));
+
+    let mut requests = mock_guard.received_requests().await;
+    let notice = requests.pop().unwrap();
+    let confirmation = requests.pop().unwrap();
+    (confirmation, notice)
+}
+
+fn email_json(request: &wiremock::Request) -> serde_json::Value {
+    serde_json::from_slice(&request.body).unwrap()
+}
+
+#[tokio::test]
+async fn changing_email_takes_effect_once_the_new_address_is_confirmed() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+
+    // WHEN
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+
+    // THEN
+    assert_eq!(stored_preferences(&app).await.email, "arsene@lup.in");
+    assert_eq!(email_json(&confirmation)["To"], "lupin@example.com");
+
+    let confirmation_link = app.get_confirmation_links(&confirmation).html;
+    assert_eq!(confirmation_link.path(), "/preferences/confirm-email");
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_preferences(&app).await.email, "lupin@example.com");
+
+    // The link only works once
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_old_address_is_told_about_an_email_change() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+
+    // WHEN
+    let (_, notice) = request_email_change(&app, &link, "lupin@example.com").await;
+
+    // THEN
+    assert_eq!(app.get_preferences_link(&notice), link);
+    let notice = email_json(&notice);
+    assert_eq!(notice["To"], "arsene@lup.in");
+    assert_eq!(notice["Subject"], "Your email address is about to change");
+    assert!(notice["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("lupin@example.com"));
+}
+
+#[tokio::test]
+async fn saving_the_current_address_calls_off_a_pending_email_change() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    let confirmation_link = app.get_confirmation_links(&confirmation).html;
+
+    // WHEN
+    app.post_preferences(&link, &preferences_form("arsene lupin", "arsene@lup.in"))
+        .await;
+
+    // THEN
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(stored_preferences(&app).await.email, "arsene@lup.in");
+}
+
+#[tokio::test]
+async fn only_the_latest_email_change_can_be_confirmed() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (first, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    let (second, _) = request_email_change(&app, &link, "arsene@example.com").await;
+
+    // WHEN
+    let first_response = reqwest::get(app.get_confirmation_links(&first).html)
+        .await
+        .unwrap();
+    let second_response = reqwest::get(app.get_confirmation_links(&second).html)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(first_response.status().as_u16(), 401);
+    assert_eq!(second_response.status().as_u16(), 200);
+    assert_eq!(stored_preferences(&app).await.email, "arsene@example.com");
+}
+
+#[tokio::test]
+async fn an_expired_email_change_link_is_rejected_with_410() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+
+    // WHEN
+    let response = reqwest::get(app.get_confirmation_links(&confirmation).html)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(stored_preferences(&app).await.email, "arsene@lup.in");
+}
+
+#[tokio::test]
+async fn confirming_an_address_taken_in_the_meantime_is_a_409() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (confirmation, _) = request_email_change(&app, &link, "ursula_le_guin@gmail.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // WHEN
+    let response = reqwest::get(app.get_confirmation_links(&confirmation).html)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "ursula_le_guin@gmail.com is already subscribed."
+    );
+    let n_subscribers = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "n!" FROM subscriptions WHERE email = 'arsene@lup.in'"#
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap();
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn an_email_change_link_does_not_confirm_a_subscription() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    let mut confirmation_link = app.get_confirmation_links(&confirmation).html;
+
+    // WHEN
+    confirmation_link.set_path("/subscribe/confirm");
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn changing_to_an_address_that_is_already_subscribed_is_refused() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // WHEN
+    let response = app
+        .post_preferences(
+            &link,
+            &preferences_form("arsene lupin", "ursula_le_guin@gmail.com"),
+        )
+        .await;
+
+    // THEN
+    assert_is_redirect_to(&response, &redirect_target(&link));
+    let html_page = app.api_client.get(link).send().await.unwrap();
+    assert!(html_page
+        .text()
+        .await
+        .unwrap()
+        .contains("ursula_le_guin@gmail.com is already subscribed."));
+}
+
+#[tokio::test]
+async fn changing_to_an_address_subscribed_in_another_case_is_refused() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // WHEN
+    app.post_preferences(
+        &link,
+        &preferences_form("arsene lupin", "Ursula_Le_Guin@gmail.com"),
+    )
+    .await;
+
+    // THEN
+    let html_page = app.api_client.get(link).send().await.unwrap();
+    assert!(html_page
+        .text()
+        .await
+        .unwrap()
+        .contains("Ursula_Le_Guin@gmail.com is already subscribed."));
+}
+
+#[tokio::test]
+async fn confirming_an_address_taken_in_another_case_in_the_meantime_is_a_409() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = preferences_link(&app).await;
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'Lupin@Example.com', 'lupin', now(), 'ok')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.database)
+    .await
+    .unwrap();
+
+    // WHEN
+    let response = reqwest::get(app.get_confirmation_links(&confirmation).html)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 409);
+    let n_subscribers = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "n!" FROM subscriptions WHERE email = 'arsene@lup.in'"#
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap();
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn an_email_change_link_stops_working_once_the_subscriber_has_left() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue = publish_and_dispatch(&app, 1).await.pop().unwrap();
+    let link = app.get_preferences_link(&issue);
+    let (confirmation, _) = request_email_change(&app, &link, "lupin@example.com").await;
+    reqwest::Client::new()
+        .post(app.get_unsubscribe_link(&issue))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // WHEN
+    let response = reqwest::get(app.get_confirmation_links(&confirmation).html)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(stored_preferences(&app).await.email, "arsene@lup.in");
+}
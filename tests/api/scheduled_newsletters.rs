@@ -0,0 +1,345 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use sqlx::Executor;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+/// Freezes the test clock two hours in the past: issues due on the test clock
+/// are then also due for the delivery worker, which goes by Postgres' time.
+fn start_clock_in_the_past(app: &TestApp) {
+    app.clock.freeze_at(Utc::now() - Duration::hours(2));
+}
+
+fn in_one_hour(app: &TestApp) -> String {
+    (app.clock.now() + Duration::hours(1)).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn scheduled_newsletter_body(scheduled_at: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        },
+        "scheduled_at": scheduled_at
+    })
+}
+
+/// Schedules an issue and returns its id.
+async fn schedule_newsletter(app: &TestApp, scheduled_at: &str) -> String {
+    let response = app
+        .post_newsletters(scheduled_newsletter_body(scheduled_at))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_string()
+}
+
+async fn n_published_issues(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(
+        r#"SELECT count(*) AS "n!" FROM newsletter_issues WHERE published_at IS NOT NULL"#
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_their_send_time_comes() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    create_confirmed_subscriber(&app).await;
+    schedule_newsletter(&app, &in_one_hour(&app)).await;
+
+    // WHEN
+    let n_early = app.publish_due_issues().await;
+    app.clock.advance(Duration::hours(1));
+    let n_on_time = app.publish_due_issues().await;
+
+    // THEN
+    assert_eq!(n_early, 0);
+    assert_eq!(n_on_time, 1);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_issue_failing_to_publish_does_not_hold_back_the_others() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    let failing_issue_id = schedule_newsletter(&app, &in_one_hour(&app)).await;
+    schedule_newsletter(&app, &in_one_hour(&app)).await;
+    // Publishing the first issue now errors out
+    let trigger = format!(
+        r#"
+        CREATE FUNCTION reject_publication() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'Publication rejected';
+        END;
+        $$ LANGUAGE plpgsql;
+        CREATE TRIGGER reject_publication
+        BEFORE UPDATE ON newsletter_issues
+        FOR EACH ROW
+        WHEN (OLD.newsletter_issue_id = '{failing_issue_id}')
+        EXECUTE FUNCTION reject_publication();
+        "#
+    );
+    app.database.execute(trigger.as_str()).await.unwrap();
+
+    // WHEN
+    app.clock.advance(Duration::hours(1));
+    let n_published = app.publish_due_issues().await;
+
+    // THEN
+    assert_eq!(n_published, 1);
+    assert_eq!(n_published_issues(&app).await, 1);
+    let failing_issue = sqlx::query_scalar!(
+        "SELECT published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        uuid::Uuid::parse_str(&failing_issue_id).unwrap()
+    )
+    .fetch_one(&app.database)
+    .await
+    .unwrap();
+    assert_eq!(failing_issue, None);
+}
+
+#[tokio::test]
+async fn scheduling_an_issue_sends_nothing_right_away() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    let response = app
+        .post_newsletters(scheduled_newsletter_body(&in_one_hour(&app)))
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 202);
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(n_published_issues(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_goes_to_the_subscribers_of_its_send_time() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    schedule_newsletter(&app, &in_one_hour(&app)).await;
+    create_confirmed_subscriber(&app).await;
+
+    // WHEN
+    app.clock.advance(Duration::hours(1));
+    app.publish_due_issues().await;
+
+    // THEN
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_local_send_time_is_read_in_the_given_timezone() {
+    // GIVEN
+    let app = spawn_app().await;
+    let mut body = scheduled_newsletter_body("2099-07-01T09:00");
+    body["timezone"] = "Europe/Paris".into();
+
+    // WHEN
+    let response = app.post_newsletters(body).await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    // Paris is on UTC+2 in summer
+    assert_eq!(body["scheduled_at"], "2099-07-01T07:00:00Z");
+    let scheduled_at = sqlx::query_scalar!("SELECT scheduled_at FROM newsletter_issues")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(
+        scheduled_at,
+        Some("2099-07-01T07:00:00Z".parse::<DateTime<Utc>>().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn invalid_send_times_are_rejected_with_400() {
+    // GIVEN
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("2000-01-01T09:00:00Z", None, "a past send time"),
+        ("next tuesday", None, "an invalid date"),
+        (
+            "2099-07-01T09:00",
+            Some("Mars/Olympus_Mons"),
+            "an unknown timezone",
+        ),
+        (
+            "2099-03-29T02:30",
+            Some("Europe/Paris"),
+            "a time skipped by daylight saving time",
+        ),
+    ];
+
+    for (scheduled_at, timezone, description) in test_cases {
+        let mut body = scheduled_newsletter_body(scheduled_at);
+        body["timezone"] = timezone.into();
+
+        // WHEN
+        let response = app.post_newsletters(body).await;
+
+        // THEN
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {description}"
+        );
+    }
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn rescheduled_issues_go_out_at_their_new_send_time() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_newsletter(&app, &in_one_hour(&app)).await;
+    let new_send_time =
+        (app.clock.now() + Duration::minutes(90)).to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    // WHEN
+    let response = app
+        .put_newsletter_schedule(
+            &issue_id,
+            serde_json::json!({ "scheduled_at": new_send_time }),
+        )
+        .await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["scheduled_at"], new_send_time);
+    app.clock.advance(Duration::hours(1));
+    assert_eq!(app.publish_due_issues().await, 0);
+    app.clock.advance(Duration::minutes(30));
+    assert_eq!(app.publish_due_issues().await, 1);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_sent() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_newsletter(&app, &in_one_hour(&app)).await;
+
+    // WHEN
+    let response = app.delete_newsletter(&issue_id).await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 204);
+    app.clock.advance(Duration::hours(1));
+    assert_eq!(app.publish_due_issues().await, 0);
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn sent_issues_can_no_longer_be_cancelled_or_rescheduled() {
+    // GIVEN
+    let app = spawn_app().await;
+    start_clock_in_the_past(&app);
+    let issue_id = schedule_newsletter(&app, &in_one_hour(&app)).await;
+    app.clock.advance(Duration::hours(1));
+    app.publish_due_issues().await;
+
+    // WHEN
+    let reschedule = app
+        .put_newsletter_schedule(
+            &issue_id,
+            serde_json::json!({ "scheduled_at": in_one_hour(&app) }),
+        )
+        .await;
+    let cancel = app.delete_newsletter(&issue_id).await;
+
+    // THEN
+    assert_eq!(reschedule.status().as_u16(), 409);
+    assert_eq!(cancel.status().as_u16(), 409);
+    assert_eq!(n_published_issues(&app).await, 1);
+}
+
+#[tokio::test]
+async fn unknown_issues_are_a_404() {
+    // GIVEN
+    let app = spawn_app().await;
+    let issue_id = uuid::Uuid::new_v4().to_string();
+
+    // WHEN
+    let reschedule = app
+        .put_newsletter_schedule(
+            &issue_id,
+            serde_json::json!({ "scheduled_at": in_one_hour(&app) }),
+        )
+        .await;
+    let cancel = app.delete_newsletter(&issue_id).await;
+
+    // THEN
+    assert_eq!(reschedule.status().as_u16(), 404);
+    assert_eq!(cancel.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_only_be_changed_by_authenticated_users() {
+    // GIVEN
+    let app = spawn_app().await;
+    let issue_id = schedule_newsletter(&app, &in_one_hour(&app)).await;
+    let client = reqwest::Client::new();
+    let issue_url = format!("{}/newsletters/{issue_id}", &app.connection_string);
+
+    // WHEN
+    let reschedule = client
+        .put(format!("{issue_url}/schedule"))
+        .json(&serde_json::json!({ "scheduled_at": in_one_hour(&app) }))
+        .send()
+        .await
+        .unwrap();
+    let cancel = client.delete(&issue_url).send().await.unwrap();
+
+    // THEN
+    assert_eq!(reschedule.status().as_u16(), 401);
+    assert_eq!(cancel.status().as_u16(), 401);
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) AS "n!" FROM newsletter_issues"#)
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 1);
+}
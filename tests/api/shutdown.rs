@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn subscribe_in_background(app: &TestApp) -> tokio::task::JoinHandle<reqwest::Response> {
+    let request = app
+        .api_client
+        .post(format!("{}/subscribe", app.connection_string))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&csrf_token={}",
+            app.csrf_token().await
+        ));
+    let handle = tokio::spawn(async move { request.send().await.unwrap() });
+    // Lets the request reach the handler before shutting down
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    handle
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_during_shutdown() {
+    // GIVEN
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let in_flight = subscribe_in_background(&app).await;
+
+    // WHEN
+    app.shutdown.shutdown().await;
+
+    // THEN
+    let response = in_flight.await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn new_connections_are_refused_after_shutdown() {
+    // GIVEN
+    let app = spawn_app().await;
+
+    // WHEN
+    app.shutdown.shutdown().await;
+
+    // THEN
+    let outcome = reqwest::Client::new()
+        .get(format!("{}/livez", app.connection_string))
+        .send()
+        .await;
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_does_not_wait_for_requests_beyond_the_grace_period() {
+    // GIVEN
+    let app = spawn_app_with(|config| config.application.shutdown_grace_period_seconds = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+        .mount(&app.email_server)
+        .await;
+    let _in_flight = subscribe_in_background(&app).await;
+
+    // WHEN
+    let stopped = tokio::time::timeout(Duration::from_secs(10), app.shutdown.shutdown()).await;
+
+    // THEN
+    assert!(stopped.is_ok(), "The shutdown did not complete in time");
+}
@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 
 use crate::helpers::{email, name};
 use urlencoding::encode;
@@ -7,6 +7,8 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 use zero2prod::domain::SubscriberStatus;
+use zero2prod::pending_subscriber_cleanup::purge_expired_pending_subscribers;
+use zero2prod::routes::hash_subscription_token;
 
 fn build_body(name: &str, email: &str) -> String {
     format!("name={}&email={}", encode(name), encode(email))
@@ -41,6 +43,32 @@ async fn subscription_returns_200_on_correct_body() {
     assert_eq!(saved.email, email);
 }
 
+#[tokio::test]
+async fn subscription_time_is_read_from_the_application_clock() {
+    // GIVEN
+    let app = spawn_app().await;
+    let now = "2024-03-04T08:00:00Z".parse().unwrap();
+    app.clock.freeze_at(now);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    app.post_subscriptions(build_body(&name(), &email()))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // THEN
+    let subscribed_at = sqlx::query_scalar!("SELECT subscribed_at FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(subscribed_at, now);
+}
+
 #[tokio::test]
 async fn subscription_returns_400_on_malformed_body() {
     // GIVEN
@@ -154,7 +182,7 @@ async fn subscription_sends_a_confirmation_email_with_link() {
 }
 
 #[tokio::test]
-async fn subscribing_twice_sends_the_same_link() {
+async fn subscribing_twice_after_the_cooldown_sends_a_working_link_each_time() {
     // GIVEN
     let app = spawn_app().await;
     let name: String = name();
@@ -173,6 +201,10 @@ async fn subscribing_twice_sends_the_same_link() {
         .await
         .error_for_status()
         .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.database)
+        .await
+        .unwrap();
     app.post_subscriptions(body)
         .await
         .error_for_status()
@@ -185,8 +217,221 @@ async fn subscribing_twice_sends_the_same_link() {
         .collect();
 
     // THEN
+    assert_ne!(links[0], links[1]);
+    let response = reqwest::get(links[1].clone()).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_within_the_cooldown_is_rejected_with_429() {
+    // GIVEN
+    let app = spawn_app().await;
+    let body = build_body(&name(), &email());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.clone())
+        .await
+        .error_for_status()
+        .unwrap();
 
-    assert!(links.windows(2).all(|a| a[0] == a[1]))
+    // WHEN
+    let response = app.post_subscriptions(body).await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 120);
+}
+
+#[tokio::test]
+async fn subscriptions_are_rate_limited_per_client_ip() {
+    // GIVEN
+    let app = spawn_app_with(|config| {
+        config.application.rate_limit.subscribe_burst = 2;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    for _ in 0..2 {
+        app.post_subscriptions(build_body(&name(), &email()))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    let response = app.post_subscriptions(build_body(&name(), &email())).await;
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn the_client_ip_is_read_from_trusted_proxies() {
+    // GIVEN
+    let app = spawn_app_with(|config| {
+        config.application.rate_limit.subscribe_burst = 1;
+        config.application.rate_limit.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let csrf_token = app.csrf_token().await;
+
+    // WHEN
+    let mut statuses = vec![];
+    for client_ip in ["1.1.1.1", "2.2.2.2", "1.1.1.1"] {
+        let response = app
+            .api_client
+            .post(format!("{}/subscribe", &app.connection_string))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client_ip)
+            .header("X-CSRF-Token", &csrf_token)
+            .body(build_body(&name(), &email()))
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+
+    // THEN
+    assert_eq!(statuses, vec![200, 200, 429]);
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_not_stored_in_plaintext() {
+    // GIVEN
+    let app = spawn_app().await;
+    let body = build_body(&name(), &email());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    app.post_subscriptions(body).await;
+
+    // THEN
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_links(email_request).html;
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    let stored = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_ne!(stored.subscription_token_hash, token);
+    assert_eq!(
+        stored.subscription_token_hash,
+        hash_subscription_token(&token)
+    );
+}
+
+#[tokio::test]
+async fn subscribing_again_after_the_token_expired_sends_a_fresh_link() {
+    // GIVEN
+    let app = spawn_app().await;
+    let body = build_body(&name(), &email());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+
+    // WHEN
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // THEN
+    let requests = &app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&requests[0]).html;
+    let second_link = app.get_confirmation_links(&requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn expired_pending_subscribers_are_purged() {
+    // GIVEN
+    let app = spawn_app().await;
+    let expired_email = email();
+    let pending_email = email();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(build_body(&name(), &expired_email))
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+    app.post_subscriptions(build_body(&name(), &pending_email))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // WHEN
+    let n_purged = purge_expired_pending_subscribers(&app.database)
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(n_purged, 1);
+    let remaining = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, pending_email);
 }
 
 #[tokio::test]
@@ -142,3 +142,63 @@ async fn trying_to_confirm_a_confirmed_account_returns_400() {
     // THEN
     assert_eq!(result.status(), 400);
 }
+
+#[tokio::test]
+async fn confirmations_with_an_expired_token_are_rejected_with_410() {
+    // GIVEN
+    let app = spawn_app().await;
+    let body = build_body(&name(), &email());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_confirmation_links(request);
+
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.database)
+        .await
+        .unwrap();
+
+    // WHEN
+    let response = reqwest::get(links.html).await.unwrap();
+
+    // THEN
+    assert_eq!(response.status(), 410);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.status,
+        SubscriberStatus::PendingConfirmation.to_string()
+    );
+}
+
+#[tokio::test]
+async fn confirmation_links_expire_once_their_time_to_live_is_over() {
+    // GIVEN
+    let app = spawn_app().await;
+    let body = build_body(&name(), &email());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_confirmation_links(request);
+
+    // WHEN
+    app.clock.advance(chrono::Duration::hours(25));
+    let response = reqwest::get(links.html).await.unwrap();
+
+    // THEN
+    assert_eq!(response.status(), 410);
+}
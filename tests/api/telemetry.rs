@@ -0,0 +1,101 @@
+use wiremock::{
+    matchers::{header_exists, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+use zero2prod::{
+    configuration::{OtlpProtocol, Settings, TelemetrySettings},
+    telemetry::{get_subscriber, get_tracer_provider},
+};
+
+use crate::helpers::spawn_app;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+fn telemetry_settings(collector: &MockServer) -> TelemetrySettings {
+    let mut settings = Settings::get().unwrap().telemetry;
+    settings.otlp.enabled = true;
+    settings.otlp.protocol = OtlpProtocol::Http;
+    settings.otlp.endpoint = collector.uri();
+    settings
+}
+
+#[tokio::test]
+async fn the_trace_context_of_a_request_is_propagated_to_the_email_api() {
+    // GIVEN
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header_exists("traceparent"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&csrf_token={}",
+        app.csrf_token().await
+    );
+
+    // WHEN
+    let response = app
+        .api_client
+        .post(format!("{}/subscribe", app.connection_string))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers[&"traceparent".parse().unwrap()]
+        .last()
+        .as_str();
+    // Same trace, but the parent is now one of our spans
+    assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+    assert!(!traceparent.contains("00f067aa0ba902b7"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_otlp_collector() {
+    // GIVEN
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1..)
+        .mount(&collector)
+        .await;
+    let provider = get_tracer_provider("test".into(), &telemetry_settings(&collector))
+        .unwrap()
+        .unwrap();
+    let tracer = opentelemetry::trace::TracerProvider::tracer(&provider, "test");
+    let subscriber = get_subscriber("test".into(), "info".into(), std::io::sink, Some(tracer));
+
+    // WHEN
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Exported span").in_scope(|| {});
+    });
+    // Flushing blocks until the batch has been handed over to the collector
+    let flushed = tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap();
+
+    // THEN (the mock also checks that the collector received them on drop)
+    assert!(flushed.iter().all(Result::is_ok));
+}
+
+#[tokio::test]
+async fn nothing_is_exported_when_the_exporter_is_disabled() {
+    // GIVEN
+    let collector = MockServer::start().await;
+    let mut settings = telemetry_settings(&collector);
+    settings.otlp.enabled = false;
+
+    // WHEN
+    let provider = get_tracer_provider("test".into(), &settings).unwrap();
+
+    // THEN
+    assert!(provider.is_none());
+}
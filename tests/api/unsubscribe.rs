@@ -0,0 +1,164 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::domain::SubscriberStatus;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+async fn receive_newsletter(app: &TestApp) -> wiremock::Request {
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    mock_guard.received_requests().await.pop().unwrap()
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.database)
+        .await
+        .expect("Failed to fetch subscriber")
+        .status
+}
+
+#[tokio::test]
+async fn newsletters_include_an_unsubscribe_link_and_headers() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // WHEN
+    let request = receive_newsletter(&app).await;
+
+    // THEN
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(
+        |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+    ));
+
+    let link = app.get_unsubscribe_link(&request);
+    assert_eq!(link.path(), "/unsubscribe");
+    assert!(body["HtmlBody"].as_str().unwrap().contains("/unsubscribe?"));
+    assert!(body["TextBody"].as_str().unwrap().contains("/unsubscribe?"));
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_unsubscribe_link(&receive_newsletter(&app).await);
+
+    // WHEN
+    let response = reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // THEN
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Unsubscribed.to_string()
+    );
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_does_not_unsubscribe_by_itself() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_unsubscribe_link(&receive_newsletter(&app).await);
+
+    // WHEN
+    let response = reqwest::get(link).await.unwrap();
+
+    // THEN
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Ok.to_string()
+    );
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_links_are_rejected_with_401() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = app.get_unsubscribe_link(&receive_newsletter(&app).await);
+    let tampered_query = link
+        .query_pairs()
+        .map(|(key, value)| match key.as_ref() {
+            "subscriber_id" => (key.into_owned(), uuid::Uuid::new_v4().to_string()),
+            _ => (key.into_owned(), value.into_owned()),
+        })
+        .collect::<Vec<_>>();
+    link.query_pairs_mut().clear().extend_pairs(tampered_query);
+
+    // WHEN
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // THEN
+    assert_eq!(response.status(), 401);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriberStatus::Ok.to_string()
+    );
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // GIVEN
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_unsubscribe_link(&receive_newsletter(&app).await);
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // WHEN
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // THEN (the mock asserts no email was sent)
+}